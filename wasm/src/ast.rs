@@ -0,0 +1,659 @@
+use crate::{Code, Diagnostic, Span};
+
+// --- Lexer ---
+//
+// Source is read one line at a time. A line is an optional `label:`, then an
+// instruction (`MNEMONIC operand...`) or a directive (`.NAME arg...`), then an
+// optional `; comment`. Words are case-insensitive and stored uppercased; spans
+// are character columns into the original line.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TokenKind {
+    /// A name or number: anything that isn't whitespace or punctuation below
+    Word,
+    /// `'A'`
+    Char,
+    /// An operator, parenthesis or operand prefix (`#`, `@`, `%`)
+    Punct,
+    Colon,
+    Comment,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    span: Span,
+    /// Whitespace (or the start of the line) comes right before it
+    spaced: bool,
+}
+
+const PUNCT: &[char] = &['+', '-', '*', '/', '&', '|', '^', '~', '(', ')', '#', '@', '%'];
+
+fn lex(line: &str) -> Vec<Token> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut spaced = true;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                spaced = true;
+                continue;
+            },
+            ';' => {
+                i = chars.len();
+                TokenKind::Comment
+            },
+            ':' => {
+                i += 1;
+                TokenKind::Colon
+            },
+            '\'' => {
+                i = skip_char_literal(&chars, i);
+                TokenKind::Char
+            },
+            // `<<` and `>>`; a lone `<` or `>` is a (bad) operator of its own
+            '<' | '>' => {
+                i += if chars.get(i + 1) == Some(&c) { 2 } else { 1 };
+                TokenKind::Punct
+            },
+            _ if PUNCT.contains(&c) => {
+                i += 1;
+                TokenKind::Punct
+            },
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() && !PUNCT.contains(&chars[i])
+                    && !matches!(chars[i], ':' | ';' | '\'' | '<' | '>') {
+                    i += 1;
+                }
+                TokenKind::Word
+            },
+        };
+        tokens.push(Token { kind, text: chars[start..i].iter().collect(), span: Span::new(start, i), spaced });
+        spaced = false;
+    }
+    tokens
+}
+
+/// Index just past the character literal opening at `start`, so `';'` and `' '`
+/// stay one token. An unclosed literal runs to the end of the line.
+fn skip_char_literal(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+// --- Syntax Tree ---
+
+/// A whole program, one entry per source line (blank lines included).
+#[derive(Clone, Debug, Default)]
+pub struct Source {
+    pub lines: Vec<Line>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// 1-based
+    pub number: i32,
+    pub label: Option<Label>,
+    pub statement: Option<Statement>,
+    /// The comment text, after the `;`
+    pub comment: Option<(String, Span)>,
+}
+
+/// `NAME:` - the address of the next instruction.
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub name: String,
+    pub span: Span,
+    /// The `:` after the name
+    pub colon: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum Statement {
+    Instruction(InstructionNode),
+    Directive(DirectiveNode),
+}
+
+/// An instruction as written. The mnemonic (prefix included) isn't checked here.
+#[derive(Clone, Debug)]
+pub struct InstructionNode {
+    pub mnemonic: String,
+    pub span: Span,
+    pub operands: Vec<OperandNode>,
+}
+
+/// `.NAME arg...`, with the name including the dot.
+#[derive(Clone, Debug)]
+pub struct DirectiveNode {
+    pub name: String,
+    pub span: Span,
+    pub args: Vec<OperandNode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct OperandNode {
+    /// The operand, or why it's malformed
+    pub expr: Result<OperandExpr, String>,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OperandExpr {
+    /// `R3` or `$3`
+    Register(i32),
+    /// `#37` or `@BASE+1`
+    Memory(Expr),
+    /// `%1`
+    Port(Expr),
+    /// `5`, `LABEL`, `WIDTH*2`
+    Value(Expr),
+}
+
+/// A constant expression. Symbols are labels and `.equ`/`.define` names,
+/// resolved once every label's address is known.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expr {
+    Number(i32),
+    Symbol(String, Span),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    fn from_token(s: &str) -> Option<BinaryOp> {
+        Some(match s {
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "&" => BinaryOp::And,
+            "|" => BinaryOp::Or,
+            "^" => BinaryOp::Xor,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            _ => return None,
+        })
+    }
+
+    /// Binding strength, as in C: `* /`, then `+ -`, `<< >>`, `&`, `^`, `|`.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div => 5,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Shl | BinaryOp::Shr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Xor => 1,
+            BinaryOp::Or => 0,
+        }
+    }
+}
+
+impl Expr {
+    /// Every symbol the expression refers to.
+    pub fn symbols(&self) -> Vec<(&str, Span)> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Symbol(name, span) => vec![(name.as_str(), *span)],
+            Expr::Unary(_, e) => e.symbols(),
+            Expr::Binary(_, a, b) => {
+                let mut symbols = a.symbols();
+                symbols.extend(b.symbols());
+                symbols
+            },
+        }
+    }
+}
+
+impl OperandNode {
+    /// Symbols the operand refers to.
+    pub fn symbols(&self) -> Vec<(&str, Span)> {
+        match &self.expr {
+            Ok(OperandExpr::Memory(e) | OperandExpr::Port(e) | OperandExpr::Value(e)) => e.symbols(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The bare name, when the operand is just a symbol (`LOOP`, `WIDTH`).
+    pub fn name(&self) -> Option<&str> {
+        match &self.expr {
+            Ok(OperandExpr::Value(Expr::Symbol(name, _))) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Source {
+    /// The ROM address of each line: its instruction's, or for other lines the
+    /// next instruction's (where a label there points).
+    pub fn addresses(&self) -> Vec<i32> {
+        let mut next = 0;
+        let mut addresses = Vec::with_capacity(self.lines.len());
+        for line in &self.lines {
+            addresses.push(next);
+            if matches!(line.statement, Some(Statement::Instruction(_))) {
+                next += 1;
+            }
+        }
+        addresses
+    }
+
+    /// Every label with the address it names.
+    pub fn labels(&self) -> impl Iterator<Item = (&Line, &Label, i32)> {
+        self.lines.iter()
+            .zip(self.addresses())
+            .filter_map(|(line, address)| line.label.as_ref().map(|label| (line, label, address)))
+    }
+
+    /// Every statement in order, with its line's address.
+    pub fn statements(&self) -> impl Iterator<Item = (&Line, &Statement, i32)> {
+        self.lines.iter()
+            .zip(self.addresses())
+            .filter_map(|(line, address)| line.statement.as_ref().map(|statement| (line, statement, address)))
+    }
+}
+
+// --- Parser ---
+
+/// Parse a program into lines. Lines that don't fit the grammar are reported as
+/// `Syntax` errors and kept without a statement.
+pub fn parse_source(code: &str) -> (Source, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let lines = code.lines()
+        .enumerate()
+        .map(|(i, text)| {
+            let number = (i + 1) as i32;
+            parse_source_line(text, number).unwrap_or_else(|d| {
+                diagnostics.push(d);
+                Line { number, label: None, statement: None, comment: None }
+            })
+        })
+        .collect();
+    (Source { lines }, diagnostics)
+}
+
+fn parse_source_line(text: &str, number: i32) -> Result<Line, Diagnostic> {
+    let mut tokens = lex(text);
+    let comment = match tokens.last() {
+        Some(t) if t.kind == TokenKind::Comment => tokens.pop().map(|t| (t.text[1..].to_string(), t.span)),
+        _ => None,
+    };
+
+    let mut rest = &tokens[..];
+    let mut label = None;
+    if let [name, colon, tail @ ..] = rest {
+        if name.kind == TokenKind::Word && colon.kind == TokenKind::Colon {
+            label = Some(Label { name: name.text.to_uppercase(), span: name.span, colon: colon.span });
+            rest = tail;
+        }
+    }
+    if let Some(colon) = rest.iter().find(|t| t.kind == TokenKind::Colon) {
+        return Err(Diagnostic::new(
+            Code::Syntax, number, colon.span,
+            "Unexpected ':'. A label is one word at the start of the line, like `LOOP:`.",
+        ));
+    }
+
+    let statement = match rest.split_first() {
+        None => None,
+        Some((head, _)) if head.kind != TokenKind::Word => {
+            return Err(Diagnostic::new(Code::Syntax, number, head.span, format!("Expected an instruction, found '{}'.", head.text)));
+        },
+        Some((head, operands)) => {
+            let name = head.text.to_uppercase();
+            let operands = OperandParser { tokens: operands, pos: 0, depth: 0 }.operands();
+            Some(if name.starts_with('.') {
+                Statement::Directive(DirectiveNode { name, span: head.span, args: operands })
+            } else {
+                Statement::Instruction(InstructionNode { mnemonic: name, span: head.span, operands })
+            })
+        },
+    };
+
+    Ok(Line { number, label, statement, comment })
+}
+
+/// Operands are separated by whitespace, except around binary operators:
+/// `WIDTH * 2` is one operand. A `-` with space before it but not after starts
+/// a new, negative operand, so `IMM R1 -1` still reads as two.
+struct OperandParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// Parentheses and unary operators currently open
+    depth: usize,
+}
+
+/// Deeper expressions are rejected rather than risking the stack.
+const MAX_DEPTH: usize = 64;
+
+impl OperandParser<'_> {
+    fn operands(mut self) -> Vec<OperandNode> {
+        let mut operands = Vec::new();
+        while self.pos < self.tokens.len() {
+            let start = self.pos;
+            let expr = self.operand();
+            if expr.is_err() {
+                // Skip the rest of this operand
+                self.pos = self.pos.clamp(start + 1, self.tokens.len());
+                while self.peek().is_some_and(|t| !t.spaced) {
+                    self.pos += 1;
+                }
+            }
+            let span = Span::new(self.tokens[start].span.start, self.tokens[self.pos - 1].span.end);
+            operands.push(OperandNode { expr, span });
+        }
+        operands
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn operand(&mut self) -> Result<OperandExpr, String> {
+        let token = self.peek().unwrap().clone();
+        match (token.kind, token.text.as_str()) {
+            (TokenKind::Punct, "#" | "@") => {
+                self.pos += 1;
+                self.expr(0).map(OperandExpr::Memory)
+            },
+            (TokenKind::Punct, "%") => {
+                self.pos += 1;
+                self.expr(0).map(OperandExpr::Port)
+            },
+            (TokenKind::Word, word) if register(word).is_some() => {
+                self.pos += 1;
+                if self.binary_op().is_some() {
+                    // Consume the rest so it's reported as one operand
+                    self.pos += 1;
+                    let _ = self.expr(0);
+                    return Err(format!("Register {} can't be used in an expression.", word.to_uppercase()));
+                }
+                Ok(OperandExpr::Register(register(word).unwrap()))
+            },
+            _ => self.expr(0).map(OperandExpr::Value),
+        }
+    }
+
+    /// The binary operator at the cursor, if the expression continues with one.
+    fn binary_op(&self) -> Option<BinaryOp> {
+        let token = self.peek()?;
+        if token.kind != TokenKind::Punct {
+            return None;
+        }
+        let unary_minus = token.text == "-" && token.spaced
+            && self.tokens.get(self.pos + 1).is_some_and(|t| !t.spaced);
+        if unary_minus { None } else { BinaryOp::from_token(&token.text) }
+    }
+
+    /// Precedence climbing: operators binding at least `min` tightly.
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op().filter(|op| op.precedence() >= min) {
+            self.pos += 1;
+            let rhs = self.expr(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.next().ok_or("Expected a value at the end of the line.")?.clone();
+        match (token.kind, token.text.as_str()) {
+            (TokenKind::Punct, "-" | "~" | "(") => {
+                if self.depth == MAX_DEPTH {
+                    return Err("Expression is nested too deeply.".to_string());
+                }
+                self.depth += 1;
+                let result = self.nested(&token.text);
+                self.depth -= 1;
+                result
+            },
+            (TokenKind::Char, text) => parse_char(text).map(Expr::Number),
+            (TokenKind::Word, word) if register(word).is_some() => {
+                Err(format!("Register {} can't be used in an expression.", word.to_uppercase()))
+            },
+            // Anything starting like a number must be one
+            (TokenKind::Word, word) if word.starts_with(|c: char| c.is_ascii_digit() || c == '$') => {
+                parse_number(word).map(Expr::Number)
+            },
+            (TokenKind::Word, word) => Ok(match parse_number(word) {
+                Ok(n) => Expr::Number(n),
+                Err(_) => Expr::Symbol(word.to_uppercase(), token.span),
+            }),
+            (_, text) => Err(format!("Expected a value, found '{}'.", text)),
+        }
+    }
+
+    /// What follows a unary operator or an opening parenthesis.
+    fn nested(&mut self, open: &str) -> Result<Expr, String> {
+        match open {
+            "-" => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            "~" => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            _ => {
+                let inner = self.expr(0)?;
+                match self.next() {
+                    Some(t) if t.text == ")" => Ok(inner),
+                    _ => Err("Missing ')'.".to_string()),
+                }
+            },
+        }
+    }
+}
+
+/// `R3`, or `$` and a single digit (anything longer after `$` is hex).
+fn register(word: &str) -> Option<i32> {
+    let mut chars = word.chars();
+    match chars.next()? {
+        'R' | 'r' => parse_number(chars.as_str()).ok(),
+        '$' if chars.as_str().len() == 1 => chars.as_str().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A number: decimal, `b`/`0b` binary, `0x`/`$` hex, `0o` octal or a `'c'`
+/// character. `_` may separate digits and a leading `-` negates.
+pub fn parse_number(s: &str) -> Result<i32, String> {
+    if s.starts_with('\'') {
+        return parse_char(s);
+    }
+    let (negative, body) = match s.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, s),
+    };
+    let clean = body.replace('_', "").to_uppercase();
+    let (radix, name, digits) = if let Some(d) = clean.strip_prefix("0X").or_else(|| clean.strip_prefix('$')) {
+        (16, "hex", d)
+    } else if let Some(d) = clean.strip_prefix("0O") {
+        (8, "octal", d)
+    } else if let Some(d) = clean.strip_prefix("0B").or_else(|| clean.strip_prefix('B')) {
+        (2, "binary", d)
+    } else {
+        (10, "number", clean.as_str())
+    };
+    // from_str_radix would take a second sign
+    if digits.starts_with(['+', '-']) {
+        return Err(format!("Invalid {}: {}", name, s));
+    }
+    i32::from_str_radix(digits, radix)
+        .map(|n| if negative { -n } else { n })
+        .map_err(|_| format!("Invalid {}: {}", name, s))
+}
+
+/// `'A'`, or an escape: `'\n'`, `'\t'`, `'\0'`, `'\\'`, `'\''`. Must fit in a byte.
+fn parse_char(s: &str) -> Result<i32, String> {
+    let invalid = || format!("Invalid character literal: {}", s);
+    let inner = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')).ok_or_else(invalid)?;
+    let mut chars = inner.chars();
+    let c = match (chars.next(), chars.next(), chars.next()) {
+        (Some('\\'), Some(escape), None) => match escape {
+            'n' => '\n',
+            't' => '\t',
+            '0' => '\0',
+            '\\' | '\'' => escape,
+            _ => return Err(invalid()),
+        },
+        (Some(c), None, None) if c != '\\' => c,
+        _ => return Err(invalid()),
+    };
+    if c as u32 > 0xFF {
+        return Err(format!("Character {} doesn't fit in a byte.", s));
+    }
+    Ok(c as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emulator, SymbolTable};
+
+    fn line(text: &str) -> Line {
+        parse_source_line(text, 1).unwrap()
+    }
+
+    fn spans(span: Span) -> (usize, usize) {
+        (span.start, span.end)
+    }
+
+    #[test]
+    fn line_parts() {
+        let l = line("  loop: add R1 R2 ; sum");
+        let label = l.label.unwrap();
+        assert_eq!((label.name.as_str(), spans(label.span), spans(label.colon)), ("LOOP", (2, 6), (6, 7)));
+        let Some(Statement::Instruction(node)) = l.statement else { panic!("not an instruction") };
+        assert_eq!((node.mnemonic.as_str(), spans(node.span)), ("ADD", (8, 11)));
+        let operands: Vec<_> = node.operands.iter().map(|o| (o.expr.clone(), spans(o.span))).collect();
+        assert_eq!(operands, vec![
+            (Ok(OperandExpr::Register(1)), (12, 14)),
+            (Ok(OperandExpr::Register(2)), (15, 17)),
+        ]);
+        assert_eq!(l.comment.unwrap().0, " sum");
+
+        assert!(matches!(line(".bank 1").statement, Some(Statement::Directive(d)) if d.name == ".BANK"));
+        let empty = line("   ; just a comment");
+        assert!(empty.label.is_none() && empty.statement.is_none());
+        let label_only = line("end:");
+        assert!(label_only.label.is_some() && label_only.statement.is_none());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let (source, d) = parse_source("a: b: NOOP\n# R1\nNOOP\n");
+        let errors: Vec<_> = d.iter().map(|d| (d.code, d.line, spans(d.span))).collect();
+        assert_eq!(errors, vec![(Code::Syntax, 1, (4, 5)), (Code::Syntax, 2, (0, 1))]);
+        // Bad lines are kept, so line numbers still match the source
+        assert_eq!(source.lines.len(), 3);
+        assert!(source.lines[0].statement.is_none());
+    }
+
+    #[test]
+    fn addresses() {
+        let (source, _) = parse_source("a:\nNOOP\n\n.bank 0\nb: NOOP ; x\nNOOP\nc:");
+        assert_eq!(source.addresses(), vec![0, 0, 1, 1, 1, 2, 3]);
+        let labels: Vec<_> = source.labels().map(|(_, l, a)| (l.name.as_str(), a)).collect();
+        assert_eq!(labels, vec![("A", 0), ("B", 1), ("C", 3)]);
+    }
+
+    #[test]
+    fn literals() {
+        for (text, value) in [("0x1F", 31), ("$1f", 31), ("$0F", 15), ("0o17", 15), ("0b101", 5), ("b1_01", 5), ("'A'", 65), ("';'", 59), ("'\\n'", 10), ("'\\''", 39), ("-5", -5), ("1_000", 1000)] {
+            assert_eq!(parse_number(text), Ok(value), "{}", text);
+        }
+        for text in ["0x", "--1", "-+1", "0o8", "'AB'", "''", "'\\q'", "'€'"] {
+            assert!(parse_number(text).is_err(), "{}", text);
+        }
+        // `$3` is register 3 where a register goes; `$03` is always hex. A `;` in quotes isn't a comment
+        let value = |n| Ok(OperandExpr::Value(Expr::Number(n)));
+        assert_eq!(operands("IMM $3 $03"), vec![Ok(OperandExpr::Register(3)), value(3)]);
+        assert_eq!(operands("IMM R4 ';' ; c"), vec![Ok(OperandExpr::Register(4)), value(59)]);
+        assert_eq!(operands("IMM R5 12X")[1], Err("Invalid number: 12X".to_string()));
+        assert_eq!(line("IMM R4 ';' ; c").comment.map(|(text, _)| text), Some(" c".to_string()));
+    }
+
+    #[test]
+    fn characters_that_change_length_when_uppercased() {
+        let e = Emulator::new("IMM ß 1\nIMM R1 ı\n".to_string());
+        assert_eq!(e.get_errors().len(), 2);
+    }
+
+    fn operands(text: &str) -> Vec<Result<OperandExpr, String>> {
+        let Some(Statement::Instruction(node)) = line(text).statement else { panic!("not an instruction") };
+        node.operands.into_iter().map(|o| o.expr).collect()
+    }
+
+    #[test]
+    fn minus_and_spacing() {
+        // A spaced `-` right before its number starts a new operand
+        assert_eq!(operands("IMM R1 -1"), vec![
+            Ok(OperandExpr::Register(1)),
+            Ok(OperandExpr::Value(Expr::Unary(UnaryOp::Neg, Box::new(Expr::Number(1))))),
+        ]);
+        for text in ["IMM R1 N - 1", "IMM R1 N-1"] {
+            let parsed = operands(text);
+            assert_eq!(parsed.len(), 2, "{}", text);
+            assert!(matches!(&parsed[1], Ok(OperandExpr::Value(Expr::Binary(BinaryOp::Sub, ..)))), "{}", text);
+        }
+        assert_eq!(operands("IMM R1 N -1").len(), 3);
+        let e = Emulator::new(".equ N 5\nIMM R1 N -1\n".to_string());
+        assert_eq!(e.get_errors(), vec!["Line 2: IMM expects 2 operands (register, value): 1 extra operand found."]);
+    }
+
+    #[test]
+    fn precedence() {
+        // C precedence: ((1 + 2) << 1) & 6
+        let parsed = operands("JMP 1 + 2 << 1 & 6");
+        let [Ok(OperandExpr::Value(expr))] = parsed.as_slice() else { panic!("not one value: {:?}", parsed) };
+        let number = |n| Box::new(Expr::Number(n));
+        let sum = Box::new(Expr::Binary(BinaryOp::Add, number(1), number(2)));
+        let shifted = Box::new(Expr::Binary(BinaryOp::Shl, sum, number(1)));
+        assert_eq!(*expr, Expr::Binary(BinaryOp::And, shifted, number(6)));
+        assert_eq!(SymbolTable::default().eval(expr), Ok(6));
+    }
+
+    #[test]
+    fn bad_operators() {
+        for text in ["IMM R1 <", "IMM R1 >", "IMM R1 1 < 2"] {
+            let errors: Vec<_> = operands(text).into_iter().filter_map(Result::err).collect();
+            assert!(errors.iter().any(|e| e.starts_with("Expected a value, found '")), "{}: {:?}", text, errors);
+        }
+        let deep = format!("IMM R1 {}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(operands(&deep)[1], Err("Expression is nested too deeply.".to_string()));
+        assert_eq!(operands(&format!("IMM R1 {}1", "-".repeat(10_000)))[1], Err("Expression is nested too deeply.".to_string()));
+        assert!(operands(&format!("IMM R1 {}1{}", "(".repeat(64), ")".repeat(64)))[1].is_ok());
+    }
+}
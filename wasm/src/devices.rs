@@ -0,0 +1,1044 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use js_sys::{Function, Reflect};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// First port of the 8x8 display latch.
+pub const DISPLAY_PORT: u8 = 0;
+/// Number of display rows (one port per row).
+pub const DISPLAY_PORTS: u8 = 8;
+/// Port `INP` reads from. The host prompt is attached here by default.
+pub const INPUT_PORT: u8 = 8;
+/// Writing any value here presents the display's back buffer.
+pub const DISPLAY_SYNC_PORT: u8 = 9;
+/// First of the two joypad ports (latched buttons, then live buttons).
+pub const JOYPAD_PORT: u8 = 10;
+/// Each read returns a fresh random byte.
+pub const RNG_PORT: u8 = 12;
+/// First of the two beeper ports (pitch, then duty).
+pub const SOUND_PORT: u8 = 13;
+/// First of the three seven-segment ports (binary, low BCD pair, high BCD pair).
+pub const SEGMENT_PORT: u8 = 15;
+/// First of the two console ports (ASCII character, decimal number).
+pub const CONSOLE_PORT: u8 = 18;
+/// Page register selecting the RAM bank STORE/LOAD address.
+pub const BANK_PORT: u8 = 24;
+
+// --- Device Trait ---
+
+/// A piece of hardware attached to the port bus.
+///
+/// `port` is always relative to the base the device was attached at.
+pub trait Device {
+    /// Read a byte. `None` means the device has nothing yet and the CPU stalls until it does.
+    fn read(&mut self, _port: u8) -> Option<u8> {
+        Some(0)
+    }
+
+    /// Write a byte (OUT / ROUT).
+    fn write(&mut self, _port: u8, _value: u8) {}
+
+    /// Side-effect free view of a port, used for state snapshots.
+    fn peek(&self, _port: u8) -> u8 {
+        0
+    }
+
+    /// Called once per clock cycle.
+    fn tick(&mut self) {}
+
+    /// Called when a program is (re)loaded.
+    fn reset(&mut self) {}
+}
+
+/// Shared devices, so the host can keep a typed handle to something it attached.
+impl<D: Device> Device for Rc<RefCell<D>> {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        self.borrow_mut().read(port)
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        self.borrow_mut().write(port, value);
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        self.borrow().peek(port)
+    }
+
+    fn tick(&mut self) {
+        self.borrow_mut().tick();
+    }
+
+    fn reset(&mut self) {
+        self.borrow_mut().reset();
+    }
+}
+
+struct Mapping {
+    base: u8,
+    len: u8,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn offset(&self, port: u8) -> Option<u8> {
+        let offset = port.checked_sub(self.base)?;
+        if offset < self.len { Some(offset) } else { None }
+    }
+}
+
+// --- Port Bus ---
+
+/// Routes port reads and writes to attached devices.
+/// Later attachments shadow earlier ones on overlapping ports.
+#[derive(Default)]
+pub struct PortBus {
+    mappings: Vec<Mapping>,
+}
+
+impl PortBus {
+    pub fn new() -> Self {
+        Self { mappings: Vec::new() }
+    }
+
+    pub fn attach(&mut self, base: u8, len: u8, device: Box<dyn Device>) {
+        self.mappings.push(Mapping { base, len, device });
+    }
+
+    /// Removes every device attached at exactly `base`.
+    pub fn detach(&mut self, base: u8) {
+        self.mappings.retain(|m| m.base != base);
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+
+    fn find(&self, port: u8) -> Option<(&Mapping, u8)> {
+        self.mappings.iter().rev().find_map(|m| m.offset(port).map(|o| (m, o)))
+    }
+
+    fn find_mut(&mut self, port: u8) -> Option<(&mut Mapping, u8)> {
+        self.mappings.iter_mut().rev().find_map(|m| m.offset(port).map(|o| (m, o)))
+    }
+
+    /// Unmapped ports read as 0.
+    pub fn read(&mut self, port: u8) -> Option<u8> {
+        match self.find_mut(port) {
+            Some((m, offset)) => m.device.read(offset),
+            None => Some(0),
+        }
+    }
+
+    /// Writes to unmapped ports are dropped.
+    pub fn write(&mut self, port: u8, value: u8) {
+        if let Some((m, offset)) = self.find_mut(port) {
+            m.device.write(offset, value);
+        }
+    }
+
+    pub fn peek(&self, port: u8) -> u8 {
+        match self.find(port) {
+            Some((m, offset)) => m.device.peek(offset),
+            None => 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for m in &mut self.mappings {
+            m.device.tick();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for m in &mut self.mappings {
+            m.device.reset();
+        }
+    }
+}
+
+// --- Default Devices ---
+
+/// Plain output latch. Holds the last byte written to each port.
+pub struct OutputLatch {
+    values: Vec<u8>,
+}
+
+impl OutputLatch {
+    pub fn new(len: u8) -> Self {
+        Self { values: vec![0; len as usize] }
+    }
+}
+
+impl Device for OutputLatch {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        if let Some(slot) = self.values.get_mut(port as usize) {
+            *slot = value;
+        }
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        self.values.get(port as usize).copied().unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.values.fill(0);
+    }
+}
+
+/// The 8x8 LED matrix. Each port is one row, MSB is the leftmost pixel.
+///
+/// In double-buffered mode row writes land in a back buffer that only becomes
+/// visible when the frame is presented (a write to `DISPLAY_SYNC_PORT`), so
+/// animations don't tear mid-frame. Otherwise writes are visible immediately.
+pub struct LedMatrix {
+    front: [u8; 8],
+    back: [u8; 8],
+    double_buffered: bool,
+    frames: u32,
+}
+
+impl Default for LedMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LedMatrix {
+    pub fn new() -> Self {
+        Self {
+            front: [0; 8],
+            back: [0; 8],
+            double_buffered: false,
+            frames: 0,
+        }
+    }
+
+    /// The rows currently on screen.
+    pub fn framebuffer(&self) -> [u8; 8] {
+        self.front
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < 8 && y < 8 && (self.front[y] >> (7 - x)) & 1 == 1
+    }
+
+    /// Number of frames presented since the last reset.
+    pub fn frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn double_buffered(&self) -> bool {
+        self.double_buffered
+    }
+
+    pub fn set_double_buffered(&mut self, enabled: bool) {
+        if enabled && !self.double_buffered {
+            self.back = self.front;
+        }
+        self.double_buffered = enabled;
+    }
+
+    pub fn present(&mut self) {
+        if self.double_buffered {
+            self.front = self.back;
+        }
+        self.frames = self.frames.wrapping_add(1);
+    }
+}
+
+impl Device for LedMatrix {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        let rows = if self.double_buffered { &mut self.back } else { &mut self.front };
+        if let Some(row) = rows.get_mut(port as usize) {
+            *row = value;
+        }
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        self.front.get(port as usize).copied().unwrap_or(0)
+    }
+
+    // Double buffering is host configuration, so it survives a reset.
+    fn reset(&mut self) {
+        self.front = [0; 8];
+        self.back = [0; 8];
+        self.frames = 0;
+    }
+}
+
+/// Control port of an `LedMatrix`. Any write presents a frame;
+/// reads return the low byte of the frame counter.
+pub struct DisplaySync {
+    display: Rc<RefCell<LedMatrix>>,
+}
+
+impl DisplaySync {
+    pub fn new(display: Rc<RefCell<LedMatrix>>) -> Self {
+        Self { display }
+    }
+}
+
+impl Device for DisplaySync {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, _port: u8, _value: u8) {
+        self.display.borrow_mut().present();
+    }
+
+    fn peek(&self, _port: u8) -> u8 {
+        self.display.borrow().frame_count() as u8
+    }
+}
+
+/// Button bitmask set by the host at any time, read by programs without stalling.
+///
+/// Port 0 returns the buttons held now plus any pressed since the last read of
+/// that port, so a tap between two polls isn't lost. Port 1 returns only the
+/// buttons held now.
+#[derive(Default)]
+pub struct Joypad {
+    held: u8,
+    latched: u8,
+}
+
+impl Joypad {
+    pub const UP: u8 = 1 << 0;
+    pub const DOWN: u8 = 1 << 1;
+    pub const LEFT: u8 = 1 << 2;
+    pub const RIGHT: u8 = 1 << 3;
+    pub const A: u8 = 1 << 4;
+    pub const B: u8 = 1 << 5;
+    pub const START: u8 = 1 << 6;
+    pub const SELECT: u8 = 1 << 7;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.held
+    }
+
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.latched |= mask & !self.held;
+        self.held = mask;
+    }
+
+    pub fn press(&mut self, mask: u8) {
+        self.set_buttons(self.held | mask);
+    }
+
+    pub fn release(&mut self, mask: u8) {
+        self.set_buttons(self.held & !mask);
+    }
+}
+
+impl Device for Joypad {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        let value = self.peek(port);
+        if port == 0 {
+            self.latched = 0;
+        }
+        Some(value)
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        match port {
+            0 => self.held | self.latched,
+            1 => self.held,
+            _ => 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.latched = 0;
+    }
+}
+
+/// Where an `Rng` gets its seed from on reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RngMode {
+    /// Same sequence after every reset. For tests and replays.
+    Seeded(u32),
+    /// Fresh seed from the host on every reset. For live demos.
+    Entropy,
+}
+
+/// Random byte generator (xorshift32). Reads return the next byte;
+/// a write reseeds the generator with the written value.
+pub struct Rng {
+    mode: RngMode,
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(mode: RngMode) -> Self {
+        let mut rng = Self { mode, state: 0 };
+        rng.reseed();
+        rng
+    }
+
+    pub fn mode(&self) -> RngMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: RngMode) {
+        self.mode = mode;
+        self.reseed();
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+
+    fn reseed(&mut self) {
+        let seed = match self.mode {
+            RngMode::Seeded(seed) => seed,
+            RngMode::Entropy => host_entropy(),
+        };
+        self.seed(seed);
+    }
+
+    fn seed(&mut self, seed: u32) {
+        // Spread the seed over all 32 bits (murmur3's finalizer) so a one-byte
+        // seed doesn't start xorshift on a nearly-zero state
+        let mut x = seed.wrapping_add(0x9E37_79B9);
+        x = (x ^ (x >> 16)).wrapping_mul(0x85EB_CA6B);
+        x = (x ^ (x >> 13)).wrapping_mul(0xC2B2_AE35);
+        x ^= x >> 16;
+        // xorshift never leaves 0, so remap it
+        self.state = if x == 0 { 0x9E37_79B9 } else { x };
+    }
+}
+
+impl Device for Rng {
+    fn read(&mut self, _port: u8) -> Option<u8> {
+        Some(self.next_byte())
+    }
+
+    fn write(&mut self, _port: u8, value: u8) {
+        self.seed(value as u32);
+    }
+
+    fn reset(&mut self) {
+        self.reseed();
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn host_entropy() -> u32 {
+    (js_sys::Math::random() * u32::MAX as f64) as u32
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn host_entropy() -> u32 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0));
+    hasher.finish() as u32
+}
+
+/// Lowest beeper pitch, F#3, the bottom of a Minecraft note block's range.
+const BASE_PITCH_HZ: f64 = 184.997;
+const AMPLITUDE: f32 = 0.25;
+/// Pitch/duty changes kept between drains before the oldest are dropped.
+const MAX_SEGMENTS: usize = 4096;
+/// Emulated time kept between drains, so a host that never drains doesn't get
+/// an enormous buffer from the next one.
+const MAX_PENDING_SECONDS: f64 = 2.0;
+
+/// Square-wave beeper.
+///
+/// Port 0 sets the pitch in semitones above F#3, so 0-24 covers the same two
+/// octaves as a note block. Port 1 sets the duty cycle out of 256: 0 is silent,
+/// 128 is an even square wave. The device records how long each setting was held
+/// in emulated cycles, and `drain` renders that history at the host's sample rate.
+pub struct Beeper {
+    clock_hz: f64,
+    pitch: u8,
+    duty: u8,
+    /// (cycles, pitch, duty) since the last drain, oldest first
+    segments: Vec<(u32, u8, u8)>,
+    /// Total cycles in `segments`
+    pending: u64,
+    phase: f64,
+    sample_carry: f64,
+}
+
+impl Beeper {
+    pub fn new(clock_hz: f64) -> Self {
+        Self {
+            clock_hz,
+            pitch: 0,
+            duty: 0,
+            segments: Vec::new(),
+            pending: 0,
+            phase: 0.0,
+            sample_carry: 0.0,
+        }
+    }
+
+    /// Emulated clock speed, used to turn cycles into seconds.
+    pub fn set_clock_hz(&mut self, hz: f64) {
+        if hz > 0.0 {
+            self.clock_hz = hz;
+        }
+    }
+
+    pub fn frequency(pitch: u8) -> f64 {
+        BASE_PITCH_HZ * 2f64.powf(pitch as f64 / 12.0)
+    }
+
+    /// Render everything played since the last drain as mono PCM in [-1, 1].
+    pub fn drain(&mut self, sample_rate: u32) -> Vec<f32> {
+        let rate = sample_rate as f64;
+        let mut samples = Vec::new();
+
+        self.pending = 0;
+        for (cycles, pitch, duty) in self.segments.drain(..) {
+            let exact = cycles as f64 * rate / self.clock_hz + self.sample_carry;
+            let count = exact.floor();
+            self.sample_carry = exact - count;
+
+            let step = Self::frequency(pitch) / rate;
+            let high = duty as f64 / 256.0;
+            for _ in 0..count as usize {
+                if duty == 0 {
+                    samples.push(0.0);
+                    continue;
+                }
+                samples.push(if self.phase < high { AMPLITUDE } else { -AMPLITUDE });
+                self.phase = (self.phase + step).fract();
+            }
+        }
+
+        samples
+    }
+
+    /// Drop the oldest audio past `MAX_PENDING_SECONDS`.
+    fn trim(&mut self) {
+        let limit = (self.clock_hz * MAX_PENDING_SECONDS) as u64;
+        while self.pending > limit {
+            let oldest = &mut self.segments[0];
+            let dropped = (oldest.0 as u64).min(self.pending - limit);
+            oldest.0 -= dropped as u32;
+            self.pending -= dropped;
+            if oldest.0 == 0 {
+                self.segments.remove(0);
+            }
+        }
+    }
+}
+
+impl Device for Beeper {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        match port {
+            0 => self.pitch = value,
+            1 => self.duty = value,
+            _ => {}
+        }
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        match port {
+            0 => self.pitch,
+            1 => self.duty,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self) {
+        match self.segments.last_mut() {
+            Some((cycles, pitch, duty)) if *pitch == self.pitch && *duty == self.duty && *cycles < u32::MAX => {
+                *cycles += 1;
+            },
+            _ => {
+                if self.segments.len() >= MAX_SEGMENTS {
+                    self.pending -= self.segments.remove(0).0 as u64;
+                }
+                self.segments.push((1, self.pitch, self.duty));
+            }
+        }
+        self.pending += 1;
+        self.trim();
+    }
+
+    fn reset(&mut self) {
+        self.pitch = 0;
+        self.duty = 0;
+        self.segments.clear();
+        self.pending = 0;
+        self.phase = 0.0;
+        self.sample_carry = 0.0;
+    }
+}
+
+/// Segment patterns for hex digits, bit 0 = a ... bit 6 = g.
+const SEGMENT_FONT: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07,
+    0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+
+/// Four-digit seven-segment display. Digit 0 is the rightmost.
+///
+/// Port 0 shows a binary byte in decimal without leading zeros. Ports 1 and 2
+/// take packed BCD for digits 0-1 and 2-3; nibbles above 9 show as hex letters.
+#[derive(Default)]
+pub struct SevenSegment {
+    digits: [Option<u8>; 4],
+}
+
+impl SevenSegment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Digit values, rightmost first. `None` is a blank digit.
+    pub fn digits(&self) -> [Option<u8>; 4] {
+        self.digits
+    }
+
+    /// Lit segments per digit (bit 0 = a ... bit 6 = g), rightmost first.
+    pub fn segments(&self) -> [u8; 4] {
+        self.digits.map(|d| d.map_or(0, |v| SEGMENT_FONT[v as usize & 0xF]))
+    }
+
+    fn show_binary(&mut self, value: u8) {
+        self.digits = [None; 4];
+        let mut rest = value;
+        for slot in &mut self.digits {
+            *slot = Some(rest % 10);
+            rest /= 10;
+            if rest == 0 { break; }
+        }
+    }
+
+    fn show_bcd(&mut self, pair: usize, value: u8) {
+        self.digits[pair * 2] = Some(value & 0xF);
+        self.digits[pair * 2 + 1] = Some(value >> 4);
+    }
+}
+
+impl Device for SevenSegment {
+    fn write(&mut self, port: u8, value: u8) {
+        match port {
+            0 => self.show_binary(value),
+            1 => self.show_bcd(0, value),
+            2 => self.show_bcd(1, value),
+            _ => {}
+        }
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        let digit = |i: usize| self.digits[i].unwrap_or(0);
+        match port {
+            1 => digit(1) << 4 | digit(0),
+            2 => digit(3) << 4 | digit(2),
+            _ => 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.digits = [None; 4];
+    }
+}
+
+/// Characters kept by the console before the oldest are dropped.
+const CONSOLE_CAPACITY: usize = 4096;
+
+/// Append-only text output.
+///
+/// Port 0 takes ASCII: printable characters and `\n` are appended, 8 deletes the
+/// last character and 12 clears the screen. Port 1 prints a byte in decimal.
+#[derive(Default)]
+pub struct TextConsole {
+    text: String,
+}
+
+impl TextConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+        if self.text.len() > CONSOLE_CAPACITY {
+            let excess = self.text.len() - CONSOLE_CAPACITY;
+            self.text.drain(..excess);
+        }
+    }
+}
+
+impl Device for TextConsole {
+    fn write(&mut self, port: u8, value: u8) {
+        match (port, value) {
+            (0, 8) => { self.text.pop(); },
+            (0, 12) => self.clear(),
+            (0, b'\n') | (0, 0x20..=0x7E) => self.push_str(&(value as char).to_string()),
+            (1, _) => self.push_str(&value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+}
+
+/// The RAM page register. The emulator wraps it to the number of banks.
+pub struct BankSelect {
+    page: Rc<Cell<u8>>,
+}
+
+impl BankSelect {
+    pub fn new(page: Rc<Cell<u8>>) -> Self {
+        Self { page }
+    }
+}
+
+impl Device for BankSelect {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, _port: u8, value: u8) {
+        self.page.set(value);
+    }
+
+    fn peek(&self, _port: u8) -> u8 {
+        self.page.get()
+    }
+
+    fn reset(&mut self) {
+        self.page.set(0);
+    }
+}
+
+/// Blocking input answered by the host through `Emulator::resolve_input`.
+/// Reads stall until a value has been supplied, then consume it.
+pub struct HostPrompt {
+    pending: Rc<Cell<Option<u8>>>,
+}
+
+impl HostPrompt {
+    pub fn new(pending: Rc<Cell<Option<u8>>>) -> Self {
+        Self { pending }
+    }
+}
+
+impl Device for HostPrompt {
+    fn read(&mut self, _port: u8) -> Option<u8> {
+        self.pending.take()
+    }
+
+    fn peek(&self, _port: u8) -> u8 {
+        self.pending.get().unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.pending.set(None);
+    }
+}
+
+// --- JS Devices ---
+
+/// Adapts a JS object to the `Device` trait.
+///
+/// Every method is optional: `read(port)`, `write(port, value)`, `peek(port)`,
+/// `tick()` and `reset()`. A `read` returning `null`/`undefined` stalls the CPU.
+pub struct JsDevice {
+    target: JsValue,
+}
+
+impl JsDevice {
+    pub fn new(target: JsValue) -> Self {
+        Self { target }
+    }
+
+    fn method(&self, name: &str) -> Option<Function> {
+        Reflect::get(&self.target, &JsValue::from_str(name))
+            .ok()?
+            .dyn_into::<Function>()
+            .ok()
+    }
+
+    fn to_byte(value: &JsValue) -> u8 {
+        (value.as_f64().unwrap_or(0.0) as i32 & 0xFF) as u8
+    }
+}
+
+impl Device for JsDevice {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        let Some(f) = self.method("read") else { return Some(0) };
+        let value = f.call1(&self.target, &JsValue::from(port)).unwrap_or(JsValue::NULL);
+        if value.is_null() || value.is_undefined() {
+            None
+        } else {
+            Some(Self::to_byte(&value))
+        }
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        if let Some(f) = self.method("write") {
+            let _ = f.call2(&self.target, &JsValue::from(port), &JsValue::from(value));
+        }
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        match self.method("peek") {
+            Some(f) => f.call1(&self.target, &JsValue::from(port))
+                .map(|v| Self::to_byte(&v))
+                .unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    fn tick(&mut self) {
+        if let Some(f) = self.method("tick") {
+            let _ = f.call0(&self.target);
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(f) = self.method("reset") {
+            let _ = f.call0(&self.target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator;
+
+    fn display() -> (Rc<RefCell<LedMatrix>>, DisplaySync) {
+        let display = Rc::new(RefCell::new(LedMatrix::new()));
+        let sync = DisplaySync::new(display.clone());
+        (display, sync)
+    }
+
+    #[test]
+    fn double_buffered_rows_show_on_present() {
+        let (display, mut sync) = display();
+        display.borrow_mut().set_double_buffered(true);
+        display.borrow_mut().write(2, 0x81);
+        assert_eq!(display.borrow().framebuffer()[2], 0);
+        assert_eq!(display.borrow_mut().read(2), Some(0));
+
+        sync.write(0, 0);
+        assert_eq!(display.borrow().framebuffer()[2], 0x81);
+        assert!(display.borrow().pixel(0, 2) && display.borrow().pixel(7, 2) && !display.borrow().pixel(1, 2));
+    }
+
+    #[test]
+    fn single_buffered_rows_show_at_once() {
+        let (display, _) = display();
+        display.borrow_mut().write(0, 0xF0);
+        assert_eq!(display.borrow().framebuffer()[0], 0xF0);
+    }
+
+    #[test]
+    fn presenting_counts_frames() {
+        let (display, mut sync) = display();
+        for _ in 0..3 {
+            sync.write(0, 1);
+        }
+        assert_eq!(display.borrow().frame_count(), 3);
+        assert_eq!(sync.read(0), Some(3));
+    }
+
+    #[test]
+    fn double_buffering_survives_reset() {
+        let (display, mut sync) = display();
+        display.borrow_mut().set_double_buffered(true);
+        display.borrow_mut().write(0, 0xFF);
+        sync.write(0, 0);
+        display.borrow_mut().reset();
+        assert!(display.borrow().double_buffered());
+        assert_eq!((display.borrow().framebuffer(), display.borrow().frame_count()), ([0; 8], 0));
+
+        // Through the emulator too
+        let mut emu = Emulator::new("IMM R1 255\nOUT %0 R1\n".to_string());
+        emu.set_double_buffered(true);
+        emu.reset();
+        for _ in 0..10 {
+            emu.clock();
+        }
+        assert!(emu.display().double_buffered());
+        assert_eq!(emu.display().framebuffer()[0], 0);
+    }
+
+    #[test]
+    fn joypad_latches_taps_between_reads() {
+        let mut pad = Joypad::new();
+        pad.press(Joypad::A);
+        pad.release(Joypad::A);
+        pad.press(Joypad::LEFT);
+        // The tap on A shows once on the latched port, never on the live one
+        assert_eq!(pad.read(1), Some(Joypad::LEFT));
+        assert_eq!(pad.read(0), Some(Joypad::A | Joypad::LEFT));
+        assert_eq!(pad.read(0), Some(Joypad::LEFT));
+
+        pad.press(Joypad::START);
+        pad.reset();
+        assert_eq!((pad.read(0), pad.buttons()), (Some(Joypad::LEFT | Joypad::START), Joypad::LEFT | Joypad::START));
+    }
+
+    #[test]
+    fn in_reads_the_joypad_without_stalling() {
+        let mut emu = Emulator::new("IN R1 %10\nIMM R2 1\n".to_string());
+        emu.press_buttons(Joypad::B);
+        emu.release_buttons(Joypad::B);
+        for _ in 0..10 {
+            emu.clock();
+        }
+        assert_eq!((emu.registers().read(1), emu.registers().read(2)), (Joypad::B, 1));
+    }
+
+    #[test]
+    fn seeded_rng_replays() {
+        let bytes = |rng: &mut Rng| (0..16).map(|_| rng.next_byte()).collect::<Vec<_>>();
+        let mut rng = Rng::new(RngMode::Seeded(7));
+        let first = bytes(&mut rng);
+        rng.reset();
+        assert_eq!(bytes(&mut rng), first);
+        assert_ne!(bytes(&mut Rng::new(RngMode::Seeded(8))), first);
+
+        // Small seeds and 0 still give a busy sequence, and writing a seed restarts it
+        for seed in [0, 1] {
+            let mut rng = Rng::new(RngMode::Seeded(seed));
+            let sequence = bytes(&mut rng);
+            assert!(sequence.iter().filter(|&&b| b == 0).count() < 4, "{}: {:?}", seed, sequence);
+            rng.write(0, seed as u8);
+            assert_eq!(bytes(&mut rng), sequence);
+        }
+    }
+
+    #[test]
+    fn seeded_programs_replay() {
+        let mut emu = Emulator::new("IN R1 %12\nIN R2 %12\nIN R3 %12\n".to_string());
+        emu.seed_rng(42);
+        let run = |emu: &mut Emulator| {
+            emu.reset();
+            for _ in 0..10 {
+                emu.clock();
+            }
+            (1..4).map(|r| emu.registers().read(r)).collect::<Vec<_>>()
+        };
+        let first = run(&mut emu);
+        assert_ne!(first, vec![0; 3]);
+        assert_eq!(run(&mut emu), first);
+    }
+
+    fn ticked(beeper: &mut Beeper, cycles: u32) {
+        for _ in 0..cycles {
+            beeper.tick();
+        }
+    }
+
+    #[test]
+    fn beeper_follows_the_emulated_clock() {
+        let mut beeper = Beeper::new(1000.0);
+        beeper.write(1, 128);
+        ticked(&mut beeper, 250);
+        assert_eq!(beeper.drain(8000).len(), 250 * 8000 / 1000);
+
+        // Fractions of a sample carry over to the next drain
+        beeper.set_clock_hz(3000.0);
+        ticked(&mut beeper, 4);
+        let first = beeper.drain(1000).len();
+        ticked(&mut beeper, 5);
+        assert_eq!((first, first + beeper.drain(1000).len()), (1, 3));
+    }
+
+    #[test]
+    fn beeper_duty_zero_is_silent() {
+        let mut beeper = Beeper::new(1000.0);
+        beeper.write(0, 12);
+        ticked(&mut beeper, 100);
+        beeper.write(1, 128);
+        ticked(&mut beeper, 100);
+        let samples = beeper.drain(10_000);
+        assert_eq!(samples.len(), 2000);
+        assert!(samples[..1000].iter().all(|&s| s == 0.0));
+        assert!(samples[1000..].contains(&AMPLITUDE) && samples[1000..].contains(&-AMPLITUDE));
+    }
+
+    #[test]
+    fn beeper_keeps_only_the_newest_two_seconds() {
+        let mut beeper = Beeper::new(100.0);
+        ticked(&mut beeper, 800);
+        beeper.write(1, 128);
+        ticked(&mut beeper, 200);
+        let samples = beeper.drain(100);
+        assert_eq!(samples.len(), (100.0 * MAX_PENDING_SECONDS) as usize);
+        assert!(samples.iter().all(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn seven_segment_decoding() {
+        let mut segments = SevenSegment::new();
+        segments.write(0, 205);
+        assert_eq!(segments.digits(), [Some(5), Some(0), Some(2), None]);
+        segments.write(0, 0);
+        assert_eq!(segments.digits(), [Some(0), None, None, None]);
+        assert_eq!(segments.segments(), [0x3F, 0, 0, 0]);
+
+        // BCD pairs, with nibbles above 9 as hex letters
+        segments.write(1, 0x42);
+        segments.write(2, 0xAF);
+        assert_eq!(segments.digits(), [Some(2), Some(4), Some(0xF), Some(0xA)]);
+        assert_eq!(segments.segments(), [0x5B, 0x66, 0x71, 0x77]);
+        assert_eq!((segments.peek(1), segments.peek(2)), (0x42, 0xAF));
+    }
+
+    #[test]
+    fn console_decoding() {
+        let mut console = TextConsole::new();
+        for byte in b"Hi!\x01\n" {
+            console.write(0, *byte);
+        }
+        console.write(1, 255);
+        console.write(0, 8);
+        assert_eq!(console.text(), "Hi!\n25");
+        console.write(0, 12);
+        assert_eq!(console.text(), "");
+
+        for _ in 0..CONSOLE_CAPACITY + 10 {
+            console.write(1, 7);
+        }
+        assert_eq!(console.text().len(), CONSOLE_CAPACITY);
+    }
+}
@@ -0,0 +1,196 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+// --- Diagnostics ---
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Stable identifier for each kind of diagnostic. The id (`W001`) and name
+/// (`raw-hazard`) never change meaning, so tools can key on them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Code {
+    InvalidOperation,
+    InvalidOperand,
+    PrefixNotAllowed,
+    NotInProfile,
+    InvalidDirective,
+    Unencodable,
+    InvalidRomWord,
+    InvalidRomImage,
+    Syntax,
+    OperandCount,
+    OperandType,
+    InvalidLabel,
+    DuplicateLabel,
+    RawHazard,
+    ZeroRegisterWrite,
+    ImmediateRange,
+    PortRange,
+    MemoryRange,
+    BankMismatch,
+    UnusedLabel,
+}
+
+impl Code {
+    pub fn id(self) -> &'static str {
+        match self {
+            Code::InvalidOperation => "E001",
+            Code::InvalidOperand => "E002",
+            Code::PrefixNotAllowed => "E003",
+            Code::NotInProfile => "E004",
+            Code::InvalidDirective => "E005",
+            Code::Unencodable => "E006",
+            Code::InvalidRomWord => "E007",
+            Code::InvalidRomImage => "E008",
+            Code::Syntax => "E009",
+            Code::OperandCount => "E010",
+            Code::OperandType => "E011",
+            Code::InvalidLabel => "E012",
+            Code::DuplicateLabel => "E013",
+            Code::RawHazard => "W001",
+            Code::ZeroRegisterWrite => "W002",
+            Code::ImmediateRange => "W003",
+            Code::PortRange => "W004",
+            Code::MemoryRange => "W005",
+            Code::BankMismatch => "W006",
+            Code::UnusedLabel => "W007",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::InvalidOperation => "invalid-operation",
+            Code::InvalidOperand => "invalid-operand",
+            Code::PrefixNotAllowed => "prefix-not-allowed",
+            Code::NotInProfile => "not-in-profile",
+            Code::InvalidDirective => "invalid-directive",
+            Code::Unencodable => "unencodable",
+            Code::InvalidRomWord => "invalid-rom-word",
+            Code::InvalidRomImage => "invalid-rom-image",
+            Code::Syntax => "syntax-error",
+            Code::OperandCount => "operand-count",
+            Code::OperandType => "operand-type",
+            Code::InvalidLabel => "invalid-label",
+            Code::DuplicateLabel => "duplicate-label",
+            Code::RawHazard => "raw-hazard",
+            Code::ZeroRegisterWrite => "zero-register-write",
+            Code::ImmediateRange => "immediate-range",
+            Code::PortRange => "port-range",
+            Code::MemoryRange => "memory-range",
+            Code::BankMismatch => "bank-mismatch",
+            Code::UnusedLabel => "unused-label",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        if self.id().starts_with('E') { Severity::Error } else { Severity::Warning }
+    }
+}
+
+/// Columns `start..end` of a source line, counted in characters from 0.
+/// An empty span at 0 means the whole line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// Another place in the source that explains a diagnostic.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Related {
+    pub line: i32,
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+/// Replace `span` on `line` with `text` (insert when the span is empty).
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Edit {
+    pub line: i32,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// A quick-fix: edits applied together.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Fix {
+    pub title: String,
+    pub edits: Vec<Edit>,
+}
+
+/// An error or warning about a source line. `line` is 1-based; 0 means it isn't
+/// about a line (e.g. a malformed ROM image).
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub code: Code,
+    pub line: i32,
+    pub span: Span,
+    pub message: String,
+    pub related: Vec<Related>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn new(code: Code, line: i32, span: Span, message: impl Into<String>) -> Self {
+        Self { code, line, span, message: message.into(), related: Vec::new(), fixes: Vec::new() }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.code.severity()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity() == Severity::Error
+    }
+
+    pub fn with_related(mut self, line: i32, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Related { line, start: span.start, end: span.end, message: message.into() });
+        self
+    }
+
+    /// Add a quick-fix replacing `span` on `line` with `text`.
+    pub fn with_fix(mut self, title: impl Into<String>, line: i32, span: Span, text: impl Into<String>) -> Self {
+        let edit = Edit { line, start: span.start, end: span.end, text: text.into() };
+        self.fixes.push(Fix { title: title.into(), edits: vec![edit] });
+        self
+    }
+}
+
+/// The old plain-text form, "Line N: message".
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "Line {}: {}", self.line, self.message)
+        } else {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+impl Serialize for Diagnostic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Diagnostic", 9)?;
+        s.serialize_field("code", self.code.id())?;
+        s.serialize_field("name", self.code.name())?;
+        s.serialize_field("severity", &self.severity())?;
+        s.serialize_field("line", &self.line)?;
+        s.serialize_field("start", &self.span.start)?;
+        s.serialize_field("end", &self.span.end)?;
+        s.serialize_field("message", &self.message)?;
+        s.serialize_field("related", &self.related)?;
+        s.serialize_field("fixes", &self.fixes)?;
+        s.end()
+    }
+}
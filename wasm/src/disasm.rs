@@ -0,0 +1,206 @@
+use wasm_bindgen::prelude::*;
+use std::collections::BTreeSet;
+
+use crate::{
+    decode_program, Diagnostic, Instruction, Operand, OperandKind, OperandType, Operation, OperationArgs, Unit,
+    DISPLAY_PORT, DISPLAY_PORTS,
+};
+
+// --- Disassembler ---
+//
+// Turns instructions back into source the `Parser` accepts and assembles to the
+// same program: uppercase mnemonics, `R`/`#`/`%` operands, `L<address>` labels.
+
+fn prefix(args: OperationArgs) -> &'static str {
+    match args {
+        OperationArgs::None => "",
+        OperationArgs::S => "S",
+        OperationArgs::U => "U",
+        OperationArgs::X => "X",
+    }
+}
+
+fn format_operand(operand: &Operand) -> String {
+    match operand.type_ {
+        OperandType::Register => format!("R{}", operand.data),
+        OperandType::MemoryAddress => format!("#{}", operand.data),
+        OperandType::Port => format!("%{}", operand.data),
+        OperandType::Immediate => operand.data.to_string(),
+    }
+}
+
+/// `b0010_0101`
+fn format_binary(value: u8) -> String {
+    format!("b{:04b}_{:04b}", value >> 4, value & 0xF)
+}
+
+fn label(address: i32) -> String {
+    format!("L{}", address)
+}
+
+/// Canonical source for one instruction, with jump targets as line numbers.
+pub fn format_instruction(instr: &Instruction) -> String {
+    format_with(instr, format_operand)
+}
+
+fn format_with(instr: &Instruction, format_a: impl Fn(&Operand) -> String) -> String {
+    let spec = instr.operation.spec();
+    let mut s = format!("{}{}", prefix(instr.args), spec.mnemonic);
+    if spec.needs_a(instr.args) {
+        s.push(' ');
+        s.push_str(&format_a(&instr.a));
+    }
+    if spec.needs_b() {
+        s.push(' ');
+        s.push_str(&format_operand(&instr.b));
+    }
+    s
+}
+
+/// Whether the value `IMM` loads at `index` reads better as a bit pattern: the
+/// register's next use in straight-line code is a display write or a bitwise op.
+fn is_bit_pattern(instructions: &[Instruction], index: usize) -> bool {
+    let reg = instructions[index].a.data;
+    for instr in &instructions[index + 1..] {
+        let spec = instr.operation.spec();
+        let reads_b = spec.reads_b && instr.b.type_ == OperandType::Register && instr.b.data == reg;
+        let reads_a = spec.reads_a.applies(instr.args) && instr.a.type_ == OperandType::Register && instr.a.data == reg;
+        if reads_a || reads_b {
+            return match instr.operation {
+                Operation::OUT => (DISPLAY_PORT as i32..(DISPLAY_PORT + DISPLAY_PORTS) as i32).contains(&instr.a.data),
+                Operation::AND | Operation::OR | Operation::XOR => true,
+                _ => false,
+            };
+        }
+        let writes = spec.writes_a.applies(instr.args) && instr.a.type_ == OperandType::Register && instr.a.data == reg;
+        if writes || spec.unit == Unit::Branch {
+            return false;
+        }
+    }
+    false
+}
+
+/// Source for a whole program. Every jump target inside the program (or just past
+/// its end) gets a label, and IMM values used as bit patterns are written in binary.
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    let len = instructions.len() as i32;
+    let targets: BTreeSet<i32> = instructions.iter()
+        .filter(|instr| instr.operation.spec().a == OperandKind::Label)
+        .map(|instr| instr.a.data)
+        .filter(|&target| (0..=len).contains(&target))
+        .collect();
+
+    let mut out = String::new();
+    for (i, instr) in instructions.iter().enumerate() {
+        if targets.contains(&(i as i32)) {
+            out.push_str(&label(i as i32));
+            out.push_str(": ");
+        }
+        let line = match instr.operation {
+            Operation::IMM if instr.b.type_ == OperandType::Immediate
+                && (0..=255).contains(&instr.b.data)
+                && is_bit_pattern(instructions, i) => {
+                format!("IMM {} {}", format_operand(&instr.a), format_binary(instr.b.data as u8))
+            },
+            _ => format_with(instr, |operand| match instr.operation.spec().a {
+                OperandKind::Label if targets.contains(&operand.data) => label(operand.data),
+                _ => format_operand(operand),
+            }),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    if targets.contains(&len) {
+        out.push_str(&label(len));
+        out.push_str(":\n");
+    }
+    out
+}
+
+/// Source for a raw ROM image, or the errors for words that don't decode.
+pub fn disassemble_words(words: &[u32]) -> Result<String, Vec<Diagnostic>> {
+    decode_program(words).map(|instructions| disassemble(&instructions))
+}
+
+/// Disassemble a raw ROM image (see `Emulator::load_rom`).
+#[wasm_bindgen]
+pub fn disassemble_rom(words: Vec<u32>) -> Result<String, JsValue> {
+    disassemble_words(&words).map_err(|errs| serde_wasm_bindgen::to_value(&errs).unwrap())
+}
+
+/// Canonical text for an instruction from `get_state` (pipeline view).
+#[wasm_bindgen]
+pub fn format_instr(instr: JsValue) -> Result<String, JsValue> {
+    let instr: Instruction = serde_wasm_bindgen::from_value(instr)?;
+    Ok(format_instruction(&instr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_program, AsmConfig, IsaProfile, Parser};
+
+    fn assemble(code: &str) -> Vec<Instruction> {
+        let (instructions, diagnostics) = Parser::parse(code.to_string(), &AsmConfig::default());
+        let errors: Vec<_> = diagnostics.iter().filter(|d| d.is_error()).collect();
+        assert!(errors.is_empty(), "{:?}\n{}", errors, code);
+        instructions
+    }
+
+    /// The demo programs bundled with the web UI, by name.
+    fn examples() -> Vec<(&'static str, &'static str)> {
+        let svelte = include_str!("../../src/lib/components/CpuEmulator.svelte");
+        let start = svelte.find("const EXAMPLES = {").unwrap();
+        let block = &svelte[start..start + svelte[start..].find("\n};").unwrap()];
+        // Template literals alternate with the text between them, which ends in the name
+        let parts: Vec<&str> = block.split('`').collect();
+        parts.chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| (pair[0].rsplit('"').nth(1).unwrap(), pair[1]))
+            .collect()
+    }
+
+    #[test]
+    fn examples_reassemble_to_the_same_words() {
+        let examples = examples();
+        assert_eq!(examples.len(), 8);
+        for (name, code) in examples {
+            let words = encode_program(&assemble(code)).unwrap();
+            let source = disassemble_words(&words).unwrap();
+            assert_eq!(encode_program(&assemble(&source)).unwrap(), words, "{}", name);
+        }
+    }
+
+    #[test]
+    fn jump_targets_get_labels() {
+        let instructions = assemble("JMP 3\nNOOP\nBIE 1\n");
+        assert_eq!(disassemble(&instructions), "JMP L3\nL1: NOOP\nBIE L1\nL3:\n");
+        // Past the end of the program is just a line number
+        assert_eq!(disassemble(&assemble("JMP 5\nNOOP\n")), "JMP 5\nNOOP\n");
+    }
+
+    #[test]
+    fn display_and_mask_values_are_binary() {
+        let instructions = assemble("IMM R1 165\nOUT %0 R1\nIMM R2 12\nAND R3 R2\nIMM R4 5\nADD R4 R4\nIMM R5 3\nOUT %9 R5\n");
+        assert_eq!(disassemble(&instructions), "IMM R1 b1010_0101\nOUT %0 R1\nIMM R2 b0000_1100\nAND R3 R2\nIMM R4 5\nADD R4 R4\nIMM R5 3\nOUT %9 R5\n");
+    }
+
+    #[test]
+    fn decodable_words_reassemble() {
+        let config = AsmConfig { ram_banks: 1, isa: IsaProfile::Extended };
+        let mut checked = 0;
+        for opcode in 0..64u32 {
+            for prefix in 0..4 {
+                for (a, b) in [(0, 0), (3, 5), (7, 255), (200, 1)] {
+                    let word = opcode << 18 | prefix << 16 | a << 8 | b;
+                    let Ok(source) = disassemble_words(&[word]) else { continue };
+                    let (instructions, diagnostics) = Parser::parse(source.clone(), &config);
+                    assert!(diagnostics.iter().all(|d| !d.is_error()), "{:#x}: {}", word, source);
+                    assert_eq!(encode_program(&instructions).unwrap(), vec![word], "{}", source);
+                    checked += 1;
+                }
+            }
+        }
+        assert!(checked > 100);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{Code, Diagnostic, Instruction, InstructionSpec, Operand, OperandKind, OperandType, Operation, OperationArgs, Span};
+
+// --- Machine Code ---
+//
+// The emulator's ROM word for each line:
+//
+//   23      18 17  16 15       8 7        0
+//   [ opcode ][ pfx ][    A     ][    B     ]
+//
+// The opcode comes from `OPCODES`, the prefix is 0 none / 1 S / 2 U / 3 X, and
+// each operand is one byte (registers use 0-7). Unused operand fields are 0
+// (A too, when the prefix means it's neither read nor written), so an all-zero
+// word is NOOP.
+
+/// Bits in one instruction word.
+pub const WORD_BITS: u32 = 24;
+/// Lines of program memory.
+pub const ROM_SIZE: usize = 256;
+
+/// Opcode of each instruction, by position. The base ISA comes first in its
+/// original order; new instructions are only ever appended, so saved ROM images
+/// keep decoding the same.
+const OPCODES: &[Operation] = &[
+    Operation::NOOP, Operation::IMM, Operation::MOV, Operation::ADD, Operation::ADDC, Operation::SUB,
+    Operation::OR, Operation::XOR, Operation::AND, Operation::SHR, Operation::NOT, Operation::OUT,
+    Operation::ROUT, Operation::INP, Operation::JMP, Operation::BIE, Operation::BIG, Operation::BIL,
+    Operation::BIO, Operation::STORE, Operation::LOAD, Operation::PUSH, Operation::POP, Operation::CALL,
+    Operation::RET,
+    // Added since
+    Operation::IN,
+    Operation::SHL, Operation::ROL, Operation::ROR, Operation::MUL, Operation::CMP,
+    Operation::RLOAD, Operation::RSTORE,
+    Operation::BNE, Operation::BNG, Operation::BNL, Operation::BNO, Operation::RJMP,
+    Operation::BIZ, Operation::BNZ, Operation::BIS, Operation::BNS, Operation::BIV, Operation::BNV,
+    Operation::BSL, Operation::BSG,
+];
+
+fn opcode(op: Operation) -> u32 {
+    OPCODES.iter().position(|&o| o == op).expect("every operation has an opcode") as u32
+}
+
+const OPCODE_SHIFT: u32 = 18;
+const PREFIX_SHIFT: u32 = 16;
+const A_SHIFT: u32 = 8;
+
+fn prefix_bits(args: OperationArgs) -> u32 {
+    match args {
+        OperationArgs::None => 0,
+        OperationArgs::S => 1,
+        OperationArgs::U => 2,
+        OperationArgs::X => 3,
+    }
+}
+
+fn encode_operand(kind: OperandKind, operand: &Operand) -> Result<u32, String> {
+    let value = operand.data;
+    match kind {
+        OperandKind::None => Ok(0),
+        OperandKind::Register if (0..=7).contains(&value) => Ok(value as u32),
+        OperandKind::Register => Err(format!("Register R{} does not exist.", value)),
+        _ if (0..=255).contains(&value) => Ok(value as u32),
+        // The assembler wraps values it warned about, so this only catches hand-built instructions
+        OperandKind::Immediate => Err(format!("Value {} does not fit in 8 bits.", value)),
+        OperandKind::Memory => Err(format!("Memory address #{} does not fit in 8 bits.", value)),
+        OperandKind::Port => Err(format!("Port %{} does not fit in 8 bits.", value)),
+        OperandKind::Label => Err(format!("Jump target {} is outside the 256-line ROM.", value)),
+    }
+}
+
+fn decode_operand(kind: OperandKind, bits: u32) -> Result<Operand, String> {
+    let value = bits as i32;
+    let type_ = match kind {
+        OperandKind::None if bits != 0 => return Err("Unused operand field is not 0.".to_string()),
+        OperandKind::Register if bits > 7 => return Err(format!("Register field {} is out of range (0-7).", bits)),
+        OperandKind::Register => OperandType::Register,
+        OperandKind::Memory => OperandType::MemoryAddress,
+        OperandKind::Port => OperandType::Port,
+        OperandKind::None | OperandKind::Immediate | OperandKind::Label => OperandType::Immediate,
+    };
+    Ok(Operand::new(type_, value))
+}
+
+/// What the A field holds. With some prefixes an ALU op doesn't use A at all
+/// (`XADD R2`), and then the field is 0.
+fn a_kind(spec: &InstructionSpec, args: OperationArgs) -> OperandKind {
+    if spec.needs_a(args) { spec.a } else { OperandKind::None }
+}
+
+/// Fails for a prefix the assembler can't express: `X` + `OR` reads as `XOR`.
+fn check_prefix(spec: &InstructionSpec, args: OperationArgs) -> Result<(), String> {
+    let letter = match args {
+        OperationArgs::None => return Ok(()),
+        OperationArgs::S => "S",
+        OperationArgs::U => "U",
+        OperationArgs::X => "X",
+    };
+    match Operation::from_mnemonic(&format!("{}{}", letter, spec.mnemonic)) {
+        Some(other) => Err(format!("{} with the {} prefix can't be written: it reads as {}.", spec.mnemonic, letter, other.spec().mnemonic)),
+        None => Ok(()),
+    }
+}
+
+/// The ROM word for `instr`. Operands are read according to the ISA table, so an
+/// operand written with the wrong syntax (e.g. `OUT R1 R2`) encodes as what the
+/// hardware would see. Fails if a field doesn't fit.
+pub fn encode(instr: &Instruction) -> Result<u32, String> {
+    let spec = instr.operation.spec();
+    check_prefix(spec, instr.args)?;
+    let a = encode_operand(a_kind(spec, instr.args), &instr.a)?;
+    let b = encode_operand(spec.b, &instr.b)?;
+    Ok(opcode(instr.operation) << OPCODE_SHIFT | prefix_bits(instr.args) << PREFIX_SHIFT | a << A_SHIFT | b)
+}
+
+/// The instruction held by ROM word `word` at `address`. The exact inverse of
+/// `encode`: anything `encode` can't produce is rejected.
+pub fn decode(word: u32, address: i32) -> Result<Instruction, String> {
+    if word >> WORD_BITS != 0 {
+        return Err(format!("Word {:#x} is wider than {} bits.", word, WORD_BITS));
+    }
+    let opcode = (word >> OPCODE_SHIFT) as usize;
+    let spec = OPCODES.get(opcode).ok_or(format!("Unknown opcode {}.", opcode))?.spec();
+
+    let args = match (word >> PREFIX_SHIFT) & 0b11 {
+        0 => OperationArgs::None,
+        1 => OperationArgs::S,
+        2 => OperationArgs::U,
+        _ => OperationArgs::X,
+    };
+    if args != OperationArgs::None && !spec.prefixes {
+        return Err(format!("{} does not take the S/U/X prefixes.", spec.mnemonic));
+    }
+    check_prefix(spec, args)?;
+
+    Ok(Instruction {
+        operation: spec.operation,
+        args,
+        a: decode_operand(a_kind(spec, args), (word >> A_SHIFT) & 0xFF)?,
+        b: decode_operand(spec.b, word & 0xFF)?,
+        address,
+        source_line: address + 1,
+    })
+}
+
+/// Encode a whole program, one word per ROM line. Errors are `Unencodable`, on
+/// the source line that can't be encoded.
+pub fn encode_program(instructions: &[Instruction]) -> Result<Vec<u32>, Vec<Diagnostic>> {
+    let mut words = Vec::with_capacity(instructions.len());
+    let mut errors = Vec::new();
+    if instructions.len() > ROM_SIZE {
+        errors.push(Diagnostic::new(
+            Code::Unencodable, 0, Span::default(),
+            format!("Program has {} instructions; the ROM holds {}.", instructions.len(), ROM_SIZE),
+        ));
+    }
+    for instr in instructions {
+        match encode(instr) {
+            Ok(word) => words.push(word),
+            Err(e) => errors.push(Diagnostic::new(Code::Unencodable, instr.source_line, Span::default(), e)),
+        }
+    }
+    if errors.is_empty() { Ok(words) } else { Err(errors) }
+}
+
+/// Decode a ROM image. Errors are `InvalidRomWord`, on the ROM address + 1,
+/// matching the `source_line` decoded instructions get.
+pub fn decode_program(words: &[u32]) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+    if words.len() > ROM_SIZE {
+        return Err(vec![Diagnostic::new(
+            Code::InvalidRomImage, 0, Span::default(),
+            format!("ROM image has {} words; the ROM holds {}.", words.len(), ROM_SIZE),
+        )]);
+    }
+    let mut instructions = Vec::with_capacity(words.len());
+    let mut errors = Vec::new();
+    for (address, &word) in words.iter().enumerate() {
+        match decode(word, address as i32) {
+            Ok(instr) => instructions.push(instr),
+            Err(e) => errors.push(Diagnostic::new(Code::InvalidRomWord, address as i32 + 1, Span::default(), e)),
+        }
+    }
+    if errors.is_empty() { Ok(instructions) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsmConfig, IsaProfile, Parser, ISA};
+
+    /// One line per operation and prefix, with operands as the source writes them.
+    fn every_instruction() -> String {
+        let mut code = String::from("start:\n");
+        for spec in ISA {
+            let prefixes: &[&str] = if spec.prefixes { &["", "S", "U", "X"] } else { &[""] };
+            for (&prefix, args) in prefixes.iter().zip([OperationArgs::None, OperationArgs::S, OperationArgs::U, OperationArgs::X]) {
+                let mnemonic = format!("{}{}", prefix, spec.mnemonic);
+                // X + OR is XOR
+                if !prefix.is_empty() && ISA.iter().any(|other| other.mnemonic == mnemonic) {
+                    continue;
+                }
+                let operands: Vec<&str> = spec.operands(args).iter()
+                    .map(|kind| match kind {
+                        OperandKind::Register => "R3",
+                        OperandKind::Immediate => "200",
+                        OperandKind::Memory => "#5",
+                        OperandKind::Port => "%9",
+                        OperandKind::Label => "start",
+                        OperandKind::None => "",
+                    })
+                    .collect();
+                code += &format!("{} {}\n", mnemonic, operands.join(" "));
+            }
+        }
+        code
+    }
+
+    #[test]
+    fn decode_inverts_encode() {
+        let config = AsmConfig { ram_banks: 1, isa: IsaProfile::Extended };
+        let (instructions, diagnostics) = Parser::parse(every_instruction(), &config);
+        let errors: Vec<_> = diagnostics.iter().filter(|d| d.is_error()).collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(instructions.len() > ISA.len());
+        for mut instr in instructions {
+            let word = encode(&instr).unwrap();
+            // Decoded lines are numbered by address
+            instr.source_line = instr.address + 1;
+            assert_eq!(decode(word, instr.address), Ok(instr), "{:#x}", word);
+        }
+    }
+
+    #[test]
+    fn rejects_words_encode_cannot_produce() {
+        // XADD R2 doesn't use A
+        assert!(decode(0xf0002, 0).is_ok());
+        assert!(decode(0xf0302, 0).is_err());
+        // X + OR would disassemble as XOR
+        let xor = decode(opcode(Operation::OR) << OPCODE_SHIFT | 3 << PREFIX_SHIFT | 2, 0);
+        assert_eq!(xor, Err("OR with the X prefix can't be written: it reads as XOR.".to_string()));
+    }
+
+    #[test]
+    fn opcodes_are_pinned() {
+        assert_eq!(OPCODES.len(), ISA.len());
+        for spec in ISA {
+            assert_eq!(OPCODES.iter().filter(|&&op| op == spec.operation).count(), 1, "{}", spec.mnemonic);
+        }
+        assert_eq!(opcode(Operation::NOOP), 0);
+        assert_eq!(opcode(Operation::RET), 24);
+        assert_eq!(opcode(Operation::IN), 25);
+    }
+
+    #[test]
+    fn rejects_fields_that_do_not_fit() {
+        let mut instr = Instruction::none();
+        instr.operation = Operation::IMM;
+        instr.a = Operand::new(OperandType::Register, 1);
+        instr.b = Operand::new(OperandType::Immediate, 300);
+        assert!(encode(&instr).is_err());
+        instr.b.data = -1;
+        assert!(encode(&instr).is_err());
+        assert!(decode(1 << WORD_BITS, 0).is_err());
+        assert!(decode((OPCODES.len() as u32) << OPCODE_SHIFT, 0).is_err());
+    }
+}
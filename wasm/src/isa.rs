@@ -0,0 +1,271 @@
+use wasm_bindgen::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{Operation, OperationArgs};
+use Access::{Always, Never, UnlessAcc, UnlessX};
+use OperandKind as K;
+
+// --- ISA Table ---
+//
+// Everything the assembler, hazard checks and pipeline need to know about an
+// operation, other than what it actually computes. Adding an instruction means
+// adding a row here plus its semantics in `ALU::execute` / `write_back_stage`.
+
+/// Which instructions the assembler accepts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum IsaProfile {
+    /// What the redstone hardware implements, plus `IN`, which the emulator's
+    /// devices (joypad, RNG, mailbox) need to be read without stalling.
+    Base,
+    /// Base plus the shift/multiply/compare, indirect memory and extra branch instructions.
+    Extended,
+}
+
+impl IsaProfile {
+    pub fn allows(self, op: Operation) -> bool {
+        self == IsaProfile::Extended || !op.spec().extension
+    }
+}
+
+/// What an operand slot holds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum OperandKind {
+    None,
+    Register,
+    Immediate,
+    Memory,
+    Port,
+    /// A jump target: a label or a line number.
+    Label,
+}
+
+impl OperandKind {
+    /// How the operand is written, for messages (`%port`).
+    pub fn syntax(self) -> &'static str {
+        match self {
+            OperandKind::None => "nothing",
+            OperandKind::Register => "register",
+            OperandKind::Immediate => "value",
+            OperandKind::Memory => "#address",
+            OperandKind::Port => "%port",
+            OperandKind::Label => "label",
+        }
+    }
+}
+
+/// When an instruction reads or writes its A register, depending on the prefix.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum Access {
+    Never,
+    Always,
+    /// Not with U or X, where the ACC stands in for A.
+    UnlessAcc,
+    /// Not with X, which only updates ACC and the flags.
+    UnlessX,
+}
+
+impl Access {
+    pub fn applies(self, args: OperationArgs) -> bool {
+        match self {
+            Access::Never => false,
+            Access::Always => true,
+            Access::UnlessAcc => args != OperationArgs::U && args != OperationArgs::X,
+            Access::UnlessX => args != OperationArgs::X,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum Flag {
+    Equals, Greater, Less, Overflow, Zero, Sign, SignedOverflow
+}
+
+const NO_FLAGS: &[Flag] = &[];
+/// Binary ALU ops: compare A with B, plus the result flags.
+const COMPARE_FLAGS: &[Flag] = &[
+    Flag::Equals, Flag::Greater, Flag::Less, Flag::Overflow, Flag::Zero, Flag::Sign, Flag::SignedOverflow,
+];
+/// Unary ALU ops: nothing is compared.
+const RESULT_FLAGS: &[Flag] = &[Flag::Overflow, Flag::Zero, Flag::Sign, Flag::SignedOverflow];
+
+/// The part of the CPU that carries the instruction out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum Unit {
+    Control,
+    /// Register transfers (IMM, MOV).
+    Move,
+    /// Result goes through the accumulator and is written back to A.
+    Alu,
+    Memory,
+    Io,
+    Stack,
+    Branch,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstructionSpec {
+    pub operation: Operation,
+    pub mnemonic: &'static str,
+    pub aliases: &'static [&'static str],
+    pub a: OperandKind,
+    pub b: OperandKind,
+    /// Accepts the S/U/X prefixes.
+    pub prefixes: bool,
+    #[serde(rename = "readsA")]
+    pub reads_a: Access,
+    #[serde(rename = "readsB")]
+    pub reads_b: bool,
+    #[serde(rename = "writesA")]
+    pub writes_a: Access,
+    pub flags: &'static [Flag],
+    pub unit: Unit,
+    /// Only available with `IsaProfile::Extended`.
+    pub extension: bool,
+    pub description: &'static str,
+}
+
+impl InstructionSpec {
+    /// Whether operand A is written in the source for this prefix. Prefixed ALU ops
+    /// drop A when it's neither read nor written (e.g. `XADD B`).
+    pub fn needs_a(&self, args: OperationArgs) -> bool {
+        if self.a == OperandKind::None { return false; }
+        !(self.prefixes && !self.reads_a.applies(args) && !self.writes_a.applies(args))
+    }
+
+    pub fn needs_b(&self) -> bool {
+        self.b != OperandKind::None
+    }
+
+    /// The operands written in the source for this prefix, in order.
+    pub fn operands(&self, args: OperationArgs) -> Vec<OperandKind> {
+        let a = self.needs_a(args).then_some(self.a);
+        let b = self.needs_b().then_some(self.b);
+        a.into_iter().chain(b).collect()
+    }
+
+    /// The operands as written in messages: "%port, register".
+    pub fn signature(&self, args: OperationArgs) -> String {
+        let kinds: Vec<&str> = self.operands(args).iter().map(|k| k.syntax()).collect();
+        kinds.join(", ")
+    }
+
+    pub fn is_unary(&self) -> bool {
+        self.unit == Unit::Alu && !self.flags.contains(&Flag::Equals)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn spec(
+    operation: Operation, mnemonic: &'static str, a: OperandKind, b: OperandKind,
+    reads_a: Access, reads_b: bool, writes_a: Access, unit: Unit, description: &'static str,
+) -> InstructionSpec {
+    InstructionSpec {
+        operation, mnemonic, aliases: &[], a, b,
+        prefixes: false, reads_a, reads_b, writes_a,
+        flags: NO_FLAGS, unit, extension: false, description,
+    }
+}
+
+const fn alu(operation: Operation, mnemonic: &'static str, description: &'static str) -> InstructionSpec {
+    InstructionSpec {
+        prefixes: true,
+        flags: COMPARE_FLAGS,
+        ..spec(operation, mnemonic, K::Register, K::Register, UnlessAcc, true, UnlessX, Unit::Alu, description)
+    }
+}
+
+const fn unary(operation: Operation, mnemonic: &'static str, description: &'static str) -> InstructionSpec {
+    InstructionSpec {
+        flags: RESULT_FLAGS,
+        ..spec(operation, mnemonic, K::Register, K::Register, Never, true, Always, Unit::Alu, description)
+    }
+}
+
+const fn branch(operation: Operation, mnemonic: &'static str, description: &'static str) -> InstructionSpec {
+    spec(operation, mnemonic, K::Label, K::None, Never, false, Never, Unit::Branch, description)
+}
+
+impl InstructionSpec {
+    const fn ext(self) -> Self {
+        Self { extension: true, ..self }
+    }
+}
+
+/// One row per `Operation`, in declaration order.
+pub static ISA: &[InstructionSpec] = &[
+    InstructionSpec {
+        aliases: &["NOP"],
+        ..spec(Operation::NOOP, "NOOP", K::None, K::None, Never, false, Never, Unit::Control, "No operation.")
+    },
+    spec(Operation::IMM, "IMM", K::Register, K::Immediate, Never, false, Always, Unit::Move, "Set register A to value B."),
+    spec(Operation::MOV, "MOV", K::Register, K::Register, Never, true, Always, Unit::Move, "Copy register B into A."),
+    alu(Operation::ADD, "ADD", "A = A + B"),
+    alu(Operation::ADDC, "ADDC", "A = A + B + Overflow flag"),
+    alu(Operation::SUB, "SUB", "A = A - B"),
+    alu(Operation::OR, "OR", "A = A | B"),
+    alu(Operation::XOR, "XOR", "A = A ^ B"),
+    alu(Operation::AND, "AND", "A = A & B"),
+    unary(Operation::SHR, "SHR", "A = B shifted right by 1."),
+    unary(Operation::NOT, "NOT", "A = inverted bits of B."),
+    spec(Operation::OUT, "OUT", K::Port, K::Register, Never, true, Never, Unit::Io, "Send register B to port A."),
+    spec(Operation::ROUT, "ROUT", K::Register, K::Register, Always, true, Never, Unit::Io, "Send register B to the port held in register A."),
+    spec(Operation::INP, "INP", K::Register, K::None, Never, false, Always, Unit::Io, "Wait for user input, store it in register A."),
+    // Not in the hardware, but part of Base (see `IsaProfile::Base`)
+    spec(Operation::IN, "IN", K::Register, K::Port, Never, false, Always, Unit::Io, "Read port B into register A."),
+    branch(Operation::JMP, "JMP", "Jump to A."),
+    branch(Operation::BIE, "BIE", "Jump to A if Equal."),
+    branch(Operation::BIG, "BIG", "Jump to A if Greater."),
+    branch(Operation::BIL, "BIL", "Jump to A if Less."),
+    branch(Operation::BIO, "BIO", "Jump to A if Overflow."),
+    spec(Operation::STORE, "STORE", K::Memory, K::Register, Never, true, Never, Unit::Memory, "Save register B into memory address A."),
+    spec(Operation::LOAD, "LOAD", K::Register, K::Memory, Never, false, Always, Unit::Memory, "Load memory address B into register A."),
+    spec(Operation::PUSH, "PUSH", K::Register, K::None, Always, false, Never, Unit::Stack, "Push register A onto the stack."),
+    spec(Operation::POP, "POP", K::Register, K::None, Never, false, Always, Unit::Stack, "Pop the stack into register A."),
+    branch(Operation::CALL, "CALL", "Call the function at A."),
+    spec(Operation::RET, "RET", K::None, K::None, Never, false, Never, Unit::Branch, "Return from a function."),
+
+    // Extended profile
+    unary(Operation::SHL, "SHL", "A = B shifted left by 1.").ext(),
+    unary(Operation::ROL, "ROL", "A = B rotated left by 1.").ext(),
+    unary(Operation::ROR, "ROR", "A = B rotated right by 1.").ext(),
+    alu(Operation::MUL, "MUL", "A = A * B (low 8 bits)").ext(),
+    InstructionSpec {
+        writes_a: Never,
+        ..alu(Operation::CMP, "CMP", "Set flags as SUB A B would, without changing A or ACC.").ext()
+    },
+    spec(Operation::RLOAD, "RLOAD", K::Register, K::Register, Never, true, Always, Unit::Memory, "Load the memory address held in register B into register A.").ext(),
+    spec(Operation::RSTORE, "RSTORE", K::Register, K::Register, Always, true, Never, Unit::Memory, "Save register B into the memory address held in register A.").ext(),
+    branch(Operation::BNE, "BNE", "Jump to A if not Equal.").ext(),
+    branch(Operation::BNG, "BNG", "Jump to A if not Greater.").ext(),
+    branch(Operation::BNL, "BNL", "Jump to A if not Less.").ext(),
+    branch(Operation::BNO, "BNO", "Jump to A if no Overflow.").ext(),
+    spec(Operation::RJMP, "RJMP", K::Register, K::None, Always, false, Never, Unit::Branch, "Jump to the line held in register A.").ext(),
+    branch(Operation::BIZ, "BIZ", "Jump to A if Zero.").ext(),
+    branch(Operation::BNZ, "BNZ", "Jump to A if not Zero.").ext(),
+    branch(Operation::BIS, "BIS", "Jump to A if the Sign bit is set.").ext(),
+    branch(Operation::BNS, "BNS", "Jump to A if the Sign bit is clear.").ext(),
+    branch(Operation::BIV, "BIV", "Jump to A on Signed Overflow.").ext(),
+    branch(Operation::BNV, "BNV", "Jump to A without Signed Overflow.").ext(),
+    branch(Operation::BSL, "BSL", "Jump to A if Less, signed (after SUB/CMP).").ext(),
+    branch(Operation::BSG, "BSG", "Jump to A if Greater, signed (after SUB/CMP).").ext(),
+];
+
+impl Operation {
+    pub fn spec(self) -> &'static InstructionSpec {
+        let spec = &ISA[self as usize];
+        debug_assert_eq!(spec.operation, self, "ISA table out of order");
+        spec
+    }
+
+    pub fn from_mnemonic(s: &str) -> Option<Operation> {
+        ISA.iter()
+            .find(|spec| spec.mnemonic == s || spec.aliases.contains(&s))
+            .map(|spec| spec.operation)
+    }
+}
+
+/// The ISA table for the editor's help panel.
+#[wasm_bindgen]
+pub fn get_isa() -> JsValue {
+    serde_wasm_bindgen::to_value(ISA).unwrap()
+}
@@ -1,6 +1,43 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+mod ast;
+mod devices;
+mod diagnostics;
+mod disasm;
+mod encoding;
+mod isa;
+mod rom_image;
+mod schematic;
+mod symbols;
+mod system;
+mod verilog;
+
+pub use ast::{
+    parse_source, parse_number, Source, Line, Label, Statement, InstructionNode, DirectiveNode, OperandNode,
+    OperandExpr, Expr, UnaryOp, BinaryOp,
+};
+pub use devices::{
+    Device, PortBus, OutputLatch, LedMatrix, DisplaySync, Joypad, Rng, RngMode, Beeper,
+    SevenSegment, TextConsole, BankSelect, HostPrompt, JsDevice,
+    DISPLAY_PORT, DISPLAY_PORTS, INPUT_PORT, DISPLAY_SYNC_PORT, JOYPAD_PORT, RNG_PORT, SOUND_PORT,
+    SEGMENT_PORT, CONSOLE_PORT, BANK_PORT,
+};
+pub use diagnostics::{Diagnostic, Code, Severity, Span, Related, Edit, Fix};
+pub use disasm::{disassemble, disassemble_words, disassemble_rom, format_instruction, format_instr};
+pub use encoding::{encode, decode, encode_program, decode_program, WORD_BITS, ROM_SIZE};
+pub use isa::{
+    IsaProfile, InstructionSpec, OperandKind, Access, Flag, Unit, ISA, get_isa,
+};
+pub use rom_image::{RomFormat, write_rom_image, read_rom_image};
+pub use schematic::{RomLayout, rom_schematic, default_rom_layout};
+pub use symbols::{SymbolTable, Symbol, SymbolValue};
+pub use verilog::{verilog_rom, verilog_testbench, ROM_MODULE, CPU_MODULE};
+pub use system::{System, SharedMemory, Arbitration, Conflict, MAILBOX_PORT, MAILBOX_SLOTS};
+use system::SharedRam;
 
 #[wasm_bindgen]
 pub fn init_panic_hook() {
@@ -12,8 +49,12 @@ pub fn init_panic_hook() {
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Operation {
     NOOP, IMM, MOV, ADD, ADDC, SUB, OR, XOR, AND, SHR, NOT,
-    OUT, ROUT, INP, JMP, BIE, BIG, BIL, BIO, STORE, LOAD,
-    PUSH, POP, CALL, RET
+    OUT, ROUT, INP, IN, JMP, BIE, BIG, BIL, BIO, STORE, LOAD,
+    PUSH, POP, CALL, RET,
+    // Extended profile
+    SHL, ROL, ROR, MUL, CMP, RLOAD, RSTORE,
+    BNE, BNG, BNL, BNO, RJMP,
+    BIZ, BNZ, BIS, BNS, BIV, BNV, BSL, BSG
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -29,7 +70,7 @@ pub enum OperandType {
     Port = 3
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Operand {
     #[serde(rename = "type")]
     pub type_: OperandType,
@@ -42,7 +83,7 @@ impl Operand {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Instruction {
     pub operation: Operation,
     pub args: OperationArgs,
@@ -117,10 +158,20 @@ impl Registers {
 
 #[derive(Serialize, Deserialize)]
 pub struct AluFlags {
+    /// A == B (binary ops)
     pub equals: bool,
+    /// A > B, unsigned (binary ops)
     pub greater: bool,
+    /// A < B, unsigned (binary ops)
     pub less: bool,
+    /// Unsigned carry/borrow, or the bit shifted out by a shift/rotate
     pub overflow: bool,
+    /// Result is 0
+    pub zero: bool,
+    /// Bit 7 of the result
+    pub sign: bool,
+    /// Result doesn't fit in a signed byte (-128..127)
+    pub signed_overflow: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -139,11 +190,14 @@ impl ALU {
     pub fn new() -> Self {
         Self {
             accumulator: 0,
-            flags: AluFlags { equals: false, greater: false, less: false, overflow: false },
+            flags: AluFlags {
+                equals: false, greater: false, less: false, overflow: false,
+                zero: false, sign: false, signed_overflow: false,
+            },
         }
     }
 
-    pub fn execute(&mut self, registers: &Registers, instr: &Instruction, input_register: &mut i32, waiting_for_input: &mut bool) {
+    pub fn execute(&mut self, registers: &Registers, instr: &Instruction, isa: IsaProfile) {
         let a_data = if instr.args == OperationArgs::U || instr.args == OperationArgs::X {
             self.accumulator
         } else {
@@ -152,51 +206,73 @@ impl ALU {
 
         let b_data = registers.read(instr.b.data);
 
-        
-        let mut result: i32 = 0;
         let op = instr.operation;
-
-        match op {
-            Operation::ADD => result = (a_data as i32) + (b_data as i32),
-            Operation::ADDC => {
-                let carry = if self.flags.overflow { 1 } else { 0 };
-                result = (a_data as i32) + (b_data as i32) + carry;
-            },
-            Operation::SUB => result = (a_data as i32) - (b_data as i32),
-            Operation::OR => result = (a_data as i32) | (b_data as i32),
-            Operation::XOR => result = (a_data as i32) ^ (b_data as i32),
-            Operation::AND => result = (a_data as i32) & (b_data as i32),
-            Operation::SHR => result = (b_data as i32) >> 1,
-            Operation::NOT => result = (!b_data as i32) & 0xFF,
-            Operation::INP => {
-                *waiting_for_input = true;
-                *input_register = instr.a.data;
-                result = 0;
-            },
-            _ => {}
-        }
-
-        // Store Accumulator
-        let is_alu_op = matches!(op, 
-            Operation::ADD | Operation::ADDC | Operation::SUB | 
-            Operation::OR | Operation::XOR | Operation::AND | 
-            Operation::SHR | Operation::NOT
-        );
-
-        if is_alu_op {
-            // Flags
+        let (a, b) = (a_data as i32, b_data as i32);
+        let (sa, sb) = (a_data as i8 as i32, b_data as i8 as i32);
+        let carry = if self.flags.overflow { 1 } else { 0 };
+
+        // (unsigned result, signed result). The signed one only matters for arithmetic.
+        let (result, signed_result) = match op {
+            Operation::ADD => (a + b, sa + sb),
+            Operation::ADDC => (a + b + carry, sa + sb + carry),
+            Operation::SUB | Operation::CMP => (a - b, sa - sb),
+            Operation::MUL => (a * b, sa * sb),
+            Operation::OR => (a | b, 0),
+            Operation::XOR => (a ^ b, 0),
+            Operation::AND => (a & b, 0),
+            Operation::SHR => (b >> 1, 0),
+            Operation::NOT => (!b & 0xFF, 0),
+            Operation::SHL => (b << 1, sb * 2),
+            Operation::ROL => (b_data.rotate_left(1) as i32, 0),
+            Operation::ROR => (b_data.rotate_right(1) as i32, 0),
+            _ => return,
+        };
+        let value = (result & 0xFF) as u8;
+
+        let spec = op.spec();
+        if spec.is_unary() {
+            // Nothing is compared, so equals/greater/less keep their values
+            self.flags.overflow = match op {
+                // The hardware's SHR clears overflow; only the extended ISA carries the bit out
+                Operation::SHR => isa == IsaProfile::Extended && b_data & 1 == 1,
+                Operation::ROR => b_data & 1 == 1,
+                Operation::SHL | Operation::ROL => b_data >> 7 == 1,
+                _ => false,
+            };
+        } else {
             self.flags.equals = a_data == b_data;
             self.flags.greater = a_data > b_data;
             self.flags.less = a_data < b_data;
             self.flags.overflow = !(0..=255).contains(&result);
-            
-            self.accumulator = (result & 0xFF) as u8;
+        }
+        self.flags.zero = value == 0;
+        self.flags.sign = value & 0x80 != 0;
+        self.flags.signed_overflow = !(-128..=127).contains(&signed_result);
+
+        // CMP only sets flags
+        if spec.writes_a != Access::Never {
+            self.accumulator = value;
         }
     }
 }
 
 // --- Emulator ---
 
+/// Bytes per RAM bank (what a STORE/LOAD address can reach).
+pub const BANK_SIZE: u8 = 16;
+/// Banks needed to fill the 8-bit address space.
+pub const MAX_BANKS: u8 = 16;
+
+/// Clock speed assumed for audio timing until the host sets one (matches the UI default).
+const DEFAULT_CLOCK_HZ: f64 = 10.0;
+
+/// A loaded program, as the host supplied it.
+#[derive(Clone)]
+enum Program {
+    Assembly(String),
+    Rom(Vec<u32>),
+}
+
 #[wasm_bindgen]
 pub struct Emulator {
     instructions: Vec<Instruction>,
@@ -210,15 +286,40 @@ pub struct Emulator {
 
     registers: Registers,
     alu: ALU,
-    ports_out: [u8; 8],
-    ram: [u8; 16],
-
+    bus: PortBus,
+    display: Rc<RefCell<LedMatrix>>,
+    joypad: Rc<RefCell<Joypad>>,
+    rng: Rc<RefCell<Rng>>,
+    beeper: Rc<RefCell<Beeper>>,
+    segments: Rc<RefCell<SevenSegment>>,
+    console: Rc<RefCell<TextConsole>>,
+    host_input: Rc<Cell<Option<u8>>>,
+    ram: Vec<u8>,
+    ram_banks: u8,
+    stack_bank: u8,
+    isa: IsaProfile,
+    /// Page register, written through the bank select port
+    page: Rc<Cell<u8>>,
+    /// Set when this core is part of a `System` sharing one RAM
+    shared_ram: Option<SharedRam>,
+    /// What was last loaded, so config changes can rebuild the program
+    program: Program,
+
+    /// Stalled on the host input prompt, waiting for `resolve_input`
     waiting_for_input: bool,
+    /// Stalled on a read from any other device, which resumes on its own
+    stalled: bool,
     input_register: i32,
+    input_port: u8,
+    input_latch: u8,
+
+    /// Clocks since the last reset
+    cycles: u64,
+    /// Port writes, while recording
+    port_log: Option<Vec<PortWrite>>,
 
     // Diagnostics
-    errors: Vec<String>, // Simplified for now
-    warnings: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 #[wasm_bindgen]
@@ -235,29 +336,139 @@ impl Emulator {
             writeback_reg: Instruction::none(),
             registers: Registers::new(),
             alu: ALU::new(),
-            ports_out: [0; 8],
-            ram: [0; 16],
+            bus: PortBus::new(),
+            display: Rc::new(RefCell::new(LedMatrix::new())),
+            joypad: Rc::new(RefCell::new(Joypad::new())),
+            rng: Rc::new(RefCell::new(Rng::new(RngMode::Entropy))),
+            beeper: Rc::new(RefCell::new(Beeper::new(DEFAULT_CLOCK_HZ))),
+            segments: Rc::new(RefCell::new(SevenSegment::new())),
+            console: Rc::new(RefCell::new(TextConsole::new())),
+            host_input: Rc::new(Cell::new(None)),
+            ram: vec![0; BANK_SIZE as usize],
+            ram_banks: 1,
+            stack_bank: 0,
+            isa: IsaProfile::Base,
+            page: Rc::new(Cell::new(0)),
+            shared_ram: None,
+            program: Program::Assembly(String::new()),
             waiting_for_input: false,
+            stalled: false,
             input_register: 0,
-            errors: Vec::new(),
-            warnings: Vec::new(),
+            input_port: INPUT_PORT,
+            input_latch: 0,
+            cycles: 0,
+            port_log: None,
+            diagnostics: Vec::new(),
         };
+        emu.install_default_devices();
         emu.load_program(code);
         emu
     }
 
     pub fn load_program(&mut self, code: String) {
-        self.instructions.clear();
-        self.errors.clear();
-        self.warnings.clear();
-        self.pc = 0;
-        self.sp = 15;
-        self.reset_state();
+        self.clear_program();
 
-        let (instrs, errs, warns) = Parser::parse(code);
+        let config = AsmConfig { ram_banks: self.ram_banks, isa: self.isa };
+        self.program = Program::Assembly(code.clone());
+        let (instrs, diagnostics) = Parser::parse(code, &config);
         self.instructions = instrs;
-        self.errors = errs;
-        self.warnings = warns;
+        self.diagnostics = diagnostics;
+    }
+
+    /// Run a raw ROM image (one `WORD_BITS`-bit word per line, see `encode`) instead of assembly.
+    /// Source lines reported for it are ROM addresses + 1.
+    pub fn load_rom(&mut self, words: Vec<u32>) {
+        self.clear_program();
+
+        let config = AsmConfig { ram_banks: self.ram_banks, isa: self.isa };
+        match decode_program(&words) {
+            Ok(instrs) => {
+                for instr in &instrs {
+                    if !config.isa.allows(instr.operation) {
+                        self.diagnostics.push(Diagnostic::new(
+                            Code::NotInProfile, instr.source_line, Span::default(),
+                            format!("{:?} is not part of the base ISA. Enable the extended ISA profile to use it.", instr.operation),
+                        ));
+                    }
+                    self.diagnostics.extend(Parser::check_warnings(instr, &LineSpans::default(), instr.source_line, &config));
+                }
+                if !self.diagnostics.iter().any(Diagnostic::is_error) {
+                    self.instructions = instrs;
+                }
+            },
+            Err(errs) => self.diagnostics = errs,
+        }
+        self.program = Program::Rom(words);
+    }
+
+    /// The loaded program as a gzipped Sponge `.schem` for WorldEdit. `layout` is a
+    /// `RomLayout` (undefined for the default). Fails with the `Diagnostic`s for lines
+    /// that can't be encoded, or a message for a bad layout.
+    pub fn export_schematic(&self, layout: JsValue) -> Result<Vec<u8>, JsValue> {
+        let layout: RomLayout = if layout.is_undefined() || layout.is_null() {
+            RomLayout::default()
+        } else {
+            serde_wasm_bindgen::from_value(layout)?
+        };
+        let words = encode_program(&self.instructions)
+            .map_err(|errs| serde_wasm_bindgen::to_value(&errs).unwrap())?;
+        rom_schematic(&words, &layout).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// The loaded program as canonical source (see `disassemble`).
+    pub fn disassemble(&self) -> String {
+        disassemble(&self.instructions)
+    }
+
+    /// Load a ROM image file in `format` (see `load_rom`).
+    pub fn load_rom_image(&mut self, data: Vec<u8>, format: RomFormat) {
+        match read_rom_image(&data, format) {
+            Ok(words) => self.load_rom(words),
+            Err(e) => {
+                self.load_rom(Vec::new());
+                self.diagnostics.push(Diagnostic::new(Code::InvalidRomImage, 0, Span::default(), e));
+            },
+        }
+    }
+
+    /// The loaded program as a ROM image file in `format`.
+    pub fn export_rom(&self, format: RomFormat) -> Result<Vec<u8>, JsValue> {
+        encode_program(&self.instructions)
+            .map(|words| write_rom_image(&words, format))
+            .map_err(|errs| serde_wasm_bindgen::to_value(&errs).unwrap())
+    }
+
+    /// The loaded program as a synthesizable Verilog ROM module (`ROM_MODULE`).
+    pub fn export_verilog_rom(&self) -> Result<String, JsValue> {
+        verilog_rom(&self.instructions, ROM_MODULE)
+            .map_err(|errs| serde_wasm_bindgen::to_value(&errs).unwrap())
+    }
+
+    /// A testbench checking that a Verilog CPU (`CPU_MODULE`) running this program makes
+    /// the same port writes as the emulator does in `cycles` clocks (see `record_port_writes`).
+    pub fn export_verilog_testbench(&self, cycles: u32) -> String {
+        let writes = self.record_port_writes(cycles);
+        verilog_testbench(CPU_MODULE, &writes, cycles as u64)
+    }
+
+    /// The loaded program as ROM words, or `Diagnostic`s for the lines that can't be encoded.
+    pub fn machine_code(&self) -> Result<Vec<u32>, JsValue> {
+        encode_program(&self.instructions)
+            .map_err(|errs| serde_wasm_bindgen::to_value(&errs).unwrap())
+    }
+
+    /// The ROM address of each line of the loaded assembly, or -1 for lines without an
+    /// instruction (labels, directives, comments). Empty for a ROM image.
+    pub fn line_addresses(&self) -> Vec<i32> {
+        let Program::Assembly(code) = &self.program else { return Vec::new() };
+        let (source, _) = parse_source(code);
+        source.lines.iter()
+            .zip(source.addresses())
+            .map(|(line, address)| match line.statement {
+                Some(Statement::Instruction(_)) => address,
+                _ => -1,
+            })
+            .collect()
     }
     
     fn reset_state(&mut self) {
@@ -267,13 +478,18 @@ impl Emulator {
         self.decode_reg = Instruction::none();
         self.execute_reg = Instruction::none();
         self.writeback_reg = Instruction::none();
-        self.ports_out = [0; 8];
-        self.ram = [0; 16];
+        self.bus.reset();
+        self.ram.fill(0);
+        self.page.set(0);
         self.waiting_for_input = false;
+        self.stalled = false;
+        self.cycles = 0;
     }
 
     pub fn clock(&mut self) {
-        if self.waiting_for_input { return; }
+        self.cycles += 1;
+        self.bus.tick();
+        if (self.waiting_for_input || self.stalled) && !self.poll_input() { return; }
 
         self.registers.begin_cycle();
 
@@ -289,18 +505,127 @@ impl Emulator {
 
     pub fn resolve_input(&mut self, val: i32) {
         if self.waiting_for_input {
-            self.alu.accumulator = (val & 0xFF) as u8;
-            self.waiting_for_input = false;
+            self.host_input.set(Some((val & 0xFF) as u8));
+            self.poll_input();
         }
     }
+
+    // --- Devices ---
+
+    /// Attach a JS object as a device on ports `base..base+len`.
+    /// See `JsDevice` for the methods it may implement.
+    pub fn attach_device(&mut self, base: u8, len: u8, device: JsValue) {
+        self.bus.attach(base, len, Box::new(JsDevice::new(device)));
+    }
+
+    pub fn detach_device(&mut self, base: u8) {
+        self.bus.detach(base);
+    }
+
+    /// Rows of the 8x8 display as currently shown (front buffer).
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.display.borrow().framebuffer().to_vec()
+    }
+
+    /// Frames presented through the display sync port since the program was loaded.
+    pub fn frame_count(&self) -> u32 {
+        self.display.borrow().frame_count()
+    }
+
+    /// When enabled, display writes only become visible once the program writes to the sync port.
+    pub fn set_double_buffered(&mut self, enabled: bool) {
+        self.display.borrow_mut().set_double_buffered(enabled);
+    }
+
+    /// Set the joypad buttons currently held (see `Joypad` for the bit layout).
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.joypad.borrow_mut().set_buttons(mask);
+    }
+
+    pub fn press_buttons(&mut self, mask: u8) {
+        self.joypad.borrow_mut().press(mask);
+    }
+
+    pub fn release_buttons(&mut self, mask: u8) {
+        self.joypad.borrow_mut().release(mask);
+    }
+
+    /// Make the RNG port deterministic: every reset replays the sequence for `seed`.
+    pub fn seed_rng(&mut self, seed: u32) {
+        self.rng.borrow_mut().set_mode(RngMode::Seeded(seed));
+    }
+
+    /// Reseed the RNG port from host entropy on every reset (the default).
+    pub fn use_entropy_rng(&mut self) {
+        self.rng.borrow_mut().set_mode(RngMode::Entropy);
+    }
+
+    /// Tell the beeper how fast the emulator is being clocked, so audio plays at the right speed.
+    pub fn set_clock_hz(&mut self, hz: f64) {
+        self.beeper.borrow_mut().set_clock_hz(hz);
+    }
+
+    /// Render the beeper output since the last call as mono PCM samples in [-1, 1].
+    pub fn drain_audio(&mut self, sample_rate: u32) -> Vec<f32> {
+        self.beeper.borrow_mut().drain(sample_rate)
+    }
+
+    /// Lit segments of each seven-segment digit, rightmost first (bit 0 = a ... bit 6 = g).
+    pub fn seven_segment(&self) -> Vec<u8> {
+        self.segments.borrow().segments().to_vec()
+    }
+
+    pub fn console_text(&self) -> String {
+        self.console.borrow().text().to_string()
+    }
+
+    pub fn clear_console(&mut self) {
+        self.console.borrow_mut().clear();
+    }
+
+    /// Number of 16-byte RAM banks (1-16). STORE/LOAD address the bank selected by
+    /// the page register. Reloads the current program so warnings match.
+    pub fn set_ram_banks(&mut self, banks: u8) {
+        self.ram_banks = banks.clamp(1, MAX_BANKS);
+        self.stack_bank = self.stack_bank.min(self.ram_banks - 1);
+        self.ram = vec![0; self.ram_banks as usize * BANK_SIZE as usize];
+        self.reload();
+    }
+
+    /// Bank PUSH/POP/CALL/RET use, independent of the page register.
+    pub fn set_stack_bank(&mut self, bank: u8) {
+        self.stack_bank = bank.min(self.ram_banks - 1);
+    }
+
+    pub fn ram_banks(&self) -> u8 {
+        self.ram_banks
+    }
+
+    /// Allow the extension instructions (see `IsaProfile::Extended`). Reloads the current program.
+    pub fn set_extended_isa(&mut self, enabled: bool) {
+        self.isa = if enabled { IsaProfile::Extended } else { IsaProfile::Base };
+        self.reload();
+    }
+
+    /// Drop every attached device and restore the default display latch and input prompt.
+    pub fn reset_devices(&mut self) {
+        self.bus.clear();
+        self.install_default_devices();
+    }
     
     // --- Getters for JS ---
+    /// Errors as "Line N: message" text. See `get_diagnostics` for codes and spans.
     pub fn get_errors(&self) -> Vec<String> {
-        self.errors.clone()
+        self.messages(Severity::Error)
     }
 
     pub fn get_warnings(&self) -> Vec<String> {
-        self.warnings.clone()
+        self.messages(Severity::Warning)
+    }
+
+    /// Every error and warning for the loaded program, as `Diagnostic`s.
+    pub fn get_diagnostics(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.diagnostics).unwrap()
     }
 
     pub fn get_state(&self) -> JsValue {
@@ -309,20 +634,109 @@ impl Emulator {
             sp: self.sp,
             regs: self.registers.get_all(),
             acc: self.alu.accumulator,
-            ports: self.ports_out.to_vec(),
-            ram: self.ram.to_vec(),
+            ports: (DISPLAY_PORT..DISPLAY_PORT + DISPLAY_PORTS).map(|p| self.bus.peek(p)).collect(),
+            frame: self.frame_count(),
+            ram: (0..self.ram.len()).map(|addr| self.ram_read(addr as u8)).collect(),
+            page: self.current_page(),
             flags: &self.alu.flags,
             fetch: &self.fetch_reg,
             decode: &self.decode_reg,
             execute: &self.execute_reg,
             writeback: &self.writeback_reg,
             waiting_for_input: self.waiting_for_input,
+            stalled: self.stalled,
             input_register: self.input_register,
         };
         serde_wasm_bindgen::to_value(&state).unwrap()
     }
     
     // --- Internal Pipeline ---
+    fn clear_program(&mut self) {
+        self.instructions.clear();
+        self.diagnostics.clear();
+        self.pc = 0;
+        self.sp = 15;
+        self.reset_state();
+    }
+
+    fn messages(&self, severity: Severity) -> Vec<String> {
+        self.diagnostics.iter()
+            .filter(|d| d.severity() == severity)
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    fn reload(&mut self) {
+        match self.program.clone() {
+            Program::Assembly(code) => self.load_program(code),
+            Program::Rom(words) => self.load_rom(words),
+        }
+    }
+
+    fn install_default_devices(&mut self) {
+        self.bus.attach(DISPLAY_PORT, DISPLAY_PORTS, Box::new(self.display.clone()));
+        self.bus.attach(DISPLAY_SYNC_PORT, 1, Box::new(DisplaySync::new(self.display.clone())));
+        self.bus.attach(INPUT_PORT, 1, Box::new(HostPrompt::new(self.host_input.clone())));
+        self.bus.attach(JOYPAD_PORT, 2, Box::new(self.joypad.clone()));
+        self.bus.attach(RNG_PORT, 1, Box::new(self.rng.clone()));
+        self.bus.attach(SOUND_PORT, 2, Box::new(self.beeper.clone()));
+        self.bus.attach(SEGMENT_PORT, 3, Box::new(self.segments.clone()));
+        self.bus.attach(CONSOLE_PORT, 2, Box::new(self.console.clone()));
+        self.bus.attach(BANK_PORT, 1, Box::new(BankSelect::new(self.page.clone())));
+    }
+
+    /// Try to complete a pending port read (INP / IN). Returns false while the device is not ready.
+    fn poll_input(&mut self) -> bool {
+        match self.bus.read(self.input_port) {
+            Some(val) => {
+                self.input_latch = val;
+                // INP goes through the accumulator, like the original prompt did
+                if self.execute_reg.operation == Operation::INP {
+                    self.alu.accumulator = val;
+                }
+                self.waiting_for_input = false;
+                self.stalled = false;
+                true
+            },
+            None => {
+                // Only the host prompt needs the user; other devices get ready by themselves
+                if self.input_port == INPUT_PORT {
+                    self.waiting_for_input = true;
+                } else {
+                    self.stalled = true;
+                }
+                false
+            }
+        }
+    }
+
+    fn current_page(&self) -> u8 {
+        self.page.get() % self.ram_banks
+    }
+
+    /// Absolute RAM address of a STORE/LOAD offset in the selected page.
+    fn data_addr(&self, offset: i32) -> u8 {
+        self.current_page() * BANK_SIZE + offset as u8
+    }
+
+    fn stack_addr(&self) -> u8 {
+        self.stack_bank * BANK_SIZE + self.sp as u8
+    }
+
+    fn ram_read(&self, addr: u8) -> u8 {
+        match &self.shared_ram {
+            Some(shared) => shared.memory.borrow().read(shared.core, addr),
+            None => self.ram.get(addr as usize).copied().unwrap_or(0),
+        }
+    }
+
+    fn ram_write(&mut self, addr: u8, value: u8) {
+        match &self.shared_ram {
+            Some(shared) => shared.memory.borrow_mut().write(shared.core, addr, value),
+            None => if let Some(cell) = self.ram.get_mut(addr as usize) { *cell = value },
+        }
+    }
+
     fn increment_pc(&mut self) {
         self.pc += 1;
         if self.pc >= 255 { self.pc = 0; }
@@ -345,27 +759,59 @@ impl Emulator {
         let op = self.execute_reg.operation;
 
         // Branching
-        let mut take_branch = false;
-        if op == Operation::JMP { take_branch = true; }
-        else if op == Operation::CALL { take_branch = true; }
-        else if op == Operation::BIE && self.alu.flags.equals { take_branch = true; }
-        else if op == Operation::BIG && self.alu.flags.greater { take_branch = true; }
-        else if op == Operation::BIO && self.alu.flags.overflow { take_branch = true; }
-        else if op == Operation::BIL && self.alu.flags.less { take_branch = true; }
-        else if op == Operation::RET {
+        let flags = &self.alu.flags;
+        let mut take_branch = match op {
+            Operation::JMP | Operation::CALL | Operation::RJMP => true,
+            Operation::BIE => flags.equals,
+            Operation::BIG => flags.greater,
+            Operation::BIO => flags.overflow,
+            Operation::BIL => flags.less,
+            Operation::BNE => !flags.equals,
+            Operation::BNG => !flags.greater,
+            Operation::BNO => !flags.overflow,
+            Operation::BNL => !flags.less,
+            Operation::BIZ => flags.zero,
+            Operation::BNZ => !flags.zero,
+            Operation::BIS => flags.sign,
+            Operation::BNS => !flags.sign,
+            Operation::BIV => flags.signed_overflow,
+            Operation::BNV => !flags.signed_overflow,
+            // Signed compare after SUB/CMP A B
+            Operation::BSL => flags.sign != flags.signed_overflow,
+            Operation::BSG => !flags.zero && flags.sign == flags.signed_overflow,
+            _ => false,
+        };
+        if op == Operation::RET {
             take_branch = true;
             self.sp += 1;
             if self.sp > 15 { self.sp = 0; }
-            let ret_addr = self.ram[self.sp as usize];
+            let ret_addr = self.ram_read(self.stack_addr());
             self.execute_reg.a.data = ret_addr as i32; // Hack to use common branch logic
         }
 
         if take_branch {
-            self.pc = self.execute_reg.a.data;
+            self.pc = if op == Operation::RJMP {
+                self.registers.read(self.execute_reg.a.data) as i32
+            } else {
+                self.execute_reg.a.data
+            };
             self.fetch_reg = Instruction::none(); // Flush
         }
 
-        self.alu.execute(&self.registers, &self.execute_reg, &mut self.input_register, &mut self.waiting_for_input);
+        if op == Operation::INP || op == Operation::IN {
+            self.input_register = self.execute_reg.a.data;
+            self.input_port = if op == Operation::INP { INPUT_PORT } else { self.execute_reg.b.data as u8 };
+            self.poll_input();
+        }
+
+        self.alu.execute(&self.registers, &self.execute_reg, self.isa);
+    }
+
+    fn port_write(&mut self, port: u8, value: u8) {
+        if let Some(log) = &mut self.port_log {
+            log.push(PortWrite { cycle: self.cycles, port, value });
+        }
+        self.bus.write(port, value);
     }
 
     fn write_back_stage(&mut self) {
@@ -375,69 +821,142 @@ impl Emulator {
         let b = self.writeback_reg.b.data;
         let address = self.writeback_reg.address;
 
+        let spec = op.spec();
+
         match op {
+            _ if spec.unit == Unit::Alu && spec.writes_a.applies(self.writeback_reg.args) => {
+                self.registers.write(a, self.alu.accumulator);
+            },
             Operation::IMM => self.registers.write(a, b as u8),
             Operation::MOV => {
                 let val = self.registers.read(b);
                 self.registers.write(a, val);
             },
-            Operation::ADD | Operation::ADDC | Operation::SUB | 
-            Operation::OR | Operation::XOR | Operation::AND => {
-                let args = self.writeback_reg.args;
-                if args == OperationArgs::S || args == OperationArgs::U || args == OperationArgs::None {
-                    self.registers.write(a, self.alu.accumulator);
-                }
+            Operation::INP | Operation::IN => {
+                self.registers.write(a, self.input_latch);
             },
-            Operation::SHR | Operation::NOT => {
-                self.registers.write(a, self.alu.accumulator);
+            Operation::OUT if (0..=255).contains(&a) => {
+                self.port_write(a as u8, self.registers.read(b));
             },
-            Operation::INP => {
-                self.registers.write(a, self.alu.accumulator);
+            Operation::ROUT => {
+                self.port_write(self.registers.read(a), self.registers.read(b));
             },
-            Operation::OUT => {
-                if a < 8 {
-                    self.ports_out[a as usize] = self.registers.read(b);
-                }
+            Operation::STORE if (0..16).contains(&a) => {
+                self.ram_write(self.data_addr(a), self.registers.read(b));
             },
-            Operation::ROUT => {
-                if self.registers.read(a) < 8 {
-                    self.ports_out[self.registers.read(a) as usize] = self.registers.read(b);
-                }
+            Operation::LOAD if (0..16).contains(&b) => {
+                self.registers.write(a, self.ram_read(self.data_addr(b)));
             },
-            Operation::STORE => {
-                if a < 16 {
-                    self.ram[a as usize] = self.registers.read(b);
-                }
+            Operation::RSTORE if self.registers.read(a) < BANK_SIZE => {
+                let addr = self.data_addr(self.registers.read(a) as i32);
+                self.ram_write(addr, self.registers.read(b));
             },
-            Operation::LOAD => {
-                if b < 16 {
-                    self.registers.write(a, self.ram[b as usize]);
-                }
+            Operation::RLOAD if self.registers.read(b) < BANK_SIZE => {
+                let addr = self.data_addr(self.registers.read(b) as i32);
+                self.registers.write(a, self.ram_read(addr));
             },
-            Operation::PUSH => {
-                if self.sp >= 0 {
-                    self.ram[self.sp as usize] = self.registers.read(a);
-                    self.sp -= 1;
-                    if self.sp < 0 { self.sp = 15; }
-                }
+            Operation::PUSH if self.sp >= 0 => {
+                self.ram_write(self.stack_addr(), self.registers.read(a));
+                self.sp -= 1;
+                if self.sp < 0 { self.sp = 15; }
             },
             Operation::POP => {
                 self.sp += 1;
                 if self.sp > 15 { self.sp = 0; }
-                self.registers.write(a, self.ram[self.sp as usize]);
+                self.registers.write(a, self.ram_read(self.stack_addr()));
             },
-            Operation::CALL => {
-                if self.sp >= 0 {
-                    self.ram[self.sp as usize] = (address + 1) as u8;
-                    self.sp -= 1;
-                    if self.sp < 0 { self.sp = 15; }
-                }
+            Operation::CALL if self.sp >= 0 => {
+                self.ram_write(self.stack_addr(), (address + 1) as u8);
+                self.sp -= 1;
+                if self.sp < 0 { self.sp = 15; }
             },
             _ => {}
         }
     }
 }
 
+// Rust-side API (not exported to JS)
+impl Emulator {
+    /// Attach a native device on ports `base..base+len`, shadowing anything already mapped there.
+    pub fn attach(&mut self, base: u8, len: u8, device: Box<dyn Device>) {
+        self.bus.attach(base, len, device);
+    }
+
+    pub fn bus(&self) -> &PortBus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut PortBus {
+        &mut self.bus
+    }
+
+    /// Restart the loaded program from address 0 with cleared state.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.sp = 15;
+        self.reset_state();
+    }
+
+    pub(crate) fn set_shared_ram(&mut self, shared: Option<SharedRam>) {
+        self.shared_ram = shared;
+    }
+
+    pub fn display(&self) -> Ref<'_, LedMatrix> {
+        self.display.borrow()
+    }
+
+    pub fn seven_segment_display(&self) -> Ref<'_, SevenSegment> {
+        self.segments.borrow()
+    }
+
+    pub fn console(&self) -> Ref<'_, TextConsole> {
+        self.console.borrow()
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Clocks since the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Run `cycles` clocks of the loaded program from reset and return every port
+    /// write made. Runs on a copy with the same RAM banks and ISA profile but only the
+    /// built-in devices, so this emulator is left alone. The copy's RNG is seeded (with
+    /// this one's seed, or 0) so the result is reproducible. Input the program waits
+    /// for is never supplied, so it stalls at the first `INP`.
+    pub fn record_port_writes(&self, cycles: u32) -> Vec<PortWrite> {
+        let mut copy = Emulator::new(String::new());
+        copy.ram_banks = self.ram_banks;
+        copy.ram = vec![0; self.ram.len()];
+        copy.stack_bank = self.stack_bank;
+        copy.isa = self.isa;
+        copy.seed_rng(match self.rng.borrow().mode() {
+            RngMode::Seeded(seed) => seed,
+            RngMode::Entropy => 0,
+        });
+        copy.program = self.program.clone();
+        copy.reload();
+
+        copy.port_log = Some(Vec::new());
+        for _ in 0..cycles {
+            copy.clock();
+        }
+        copy.port_log.take().unwrap_or_default()
+    }
+}
+
+/// One `OUT`/`ROUT`, as recorded by `Emulator::record_port_writes`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub struct PortWrite {
+    /// Clock (1-based since reset) whose write-back stage made the write
+    pub cycle: u64,
+    pub port: u8,
+    pub value: u8,
+}
+
 #[derive(Serialize)]
 pub struct EmulatorState<'a> {
     pub pc: i32,
@@ -445,144 +964,383 @@ pub struct EmulatorState<'a> {
     pub regs: Vec<u8>,
     pub acc: u8,
     pub ports: Vec<u8>,
+    pub frame: u32,
     pub ram: Vec<u8>,
+    pub page: u8,
     pub flags: &'a AluFlags,
     pub fetch: &'a Instruction,
     pub decode: &'a Instruction,
     pub execute: &'a Instruction,
     pub writeback: &'a Instruction,
     pub waiting_for_input: bool,
+    pub stalled: bool,
     pub input_register: i32,
 }
 
 // --- Parser ---
 
+/// Machine configuration the assembler checks programs against.
+#[derive(Clone, Copy, Debug)]
+pub struct AsmConfig {
+    /// Number of 16-byte RAM banks (1 = no banking).
+    pub ram_banks: u8,
+    pub isa: IsaProfile,
+}
+
+impl Default for AsmConfig {
+    fn default() -> Self {
+        Self { ram_banks: 1, isa: IsaProfile::Base }
+    }
+}
+
+impl AsmConfig {
+    fn ram_size(&self) -> i32 {
+        self.ram_banks as i32 * BANK_SIZE as i32
+    }
+}
+
+/// Column ranges of the parts of a parsed instruction line.
+#[derive(Clone, Copy, Debug, Default)]
+struct LineSpans {
+    op: Span,
+    a: Option<Span>,
+    b: Option<Span>,
+}
+
 struct Parser;
 
 impl Parser {
-    fn parse(code: String) -> (Vec<Instruction>, Vec<String>, Vec<String>) {
-        let lines: Vec<&str> = code.lines().collect();
-        let mut instructions = Vec::new();
-        let mut errors = Vec::new();
-        let mut warnings = Vec::new();
-        let mut labels = HashMap::new();
-        let mut addr_counter = 0;
-
-        // Pass 0: Scan labels
-        for line in &lines {
-            let clean = line.split(';').next().unwrap_or("").trim().to_uppercase();
-            if let Some(idx) = clean.find(':') {
-                if let Some(label) = clean.get(0..idx) {
-                    if !label.contains(' ') {
-                        labels.insert(label.to_string(), addr_counter);
+    fn parse(code: String, config: &AsmConfig) -> (Vec<Instruction>, Vec<Diagnostic>) {
+        let (source, mut diagnostics) = parse_source(&code);
+        let texts: Vec<&str> = code.lines().collect();
+        let mut instructions: Vec<Instruction> = Vec::new();
+        let mut spans: Vec<LineSpans> = Vec::new();
+
+        // Pass 0: Labels and constants
+        let symbols = Self::collect_symbols(&source, &mut diagnostics);
+
+        // Pass 1: Code generation
+        // Bank selected by the last .BANK, and the line it's on
+        let mut bank = (0, None);
+        for (line, statement, address) in source.statements() {
+            let source_line = line.number;
+            let node = match statement {
+                Statement::Directive(directive) => {
+                    if let Err(d) = Self::parse_directive(directive, source_line, config, &symbols, &mut bank) {
+                        diagnostics.push(d);
                     }
-                }
-                let after = clean.get(idx+1..).unwrap_or("").trim();
-                if !after.is_empty() {
-                    addr_counter += 1;
-                }
-            } else if !clean.is_empty() {
-                addr_counter += 1;
-            }
-        }
-
-        // Pass 1: Parse
-        addr_counter = 0;
-        for (i, line) in lines.iter().enumerate() {
-            let source_line = (i + 1) as i32;
-            match Self::parse_line(line, addr_counter, source_line, &labels) {
-                Ok(Some(instr)) => {
+                    continue;
+                },
+                Statement::Instruction(node) => node,
+            };
+            // A line that fails to assemble still takes its address, as a NOOP, so
+            // labels and return addresses match what the CPU fetches
+            let placeholder = Instruction { address, source_line, ..Instruction::none() };
+            match Self::parse_instruction(node, address, source_line, &symbols) {
+                Ok((instr, line_spans)) if !config.isa.allows(instr.operation) => {
+                    diagnostics.push(Diagnostic::new(
+                        Code::NotInProfile, source_line, line_spans.op,
+                        format!("{:?} is not part of the base ISA. Enable the extended ISA profile to use it.", instr.operation),
+                    ));
+                    instructions.push(placeholder);
+                    spans.push(LineSpans::default());
+                },
+                Ok((mut instr, line_spans)) => {
                     // 1. Static Warnings
-                    let mut warns = Self::check_warnings(&instr, source_line);
+                    diagnostics.extend(Self::check_warnings(&instr, &line_spans, source_line, config));
+                    diagnostics.extend(Self::resolve_bank(&mut instr, &line_spans, source_line, config, bank));
+                    Self::wrap_immediates(&mut instr);
                     
                     // 2. DYNAMIC HAZARD CHECK (Read-After-Write)
-                    if let Some(prev) = instructions.last() {
+                    if let (Some(prev), Some(prev_spans)) = (instructions.last(), spans.last()) {
                          // Check if previous instruction writes to a register
                         if let Some(written_reg) = Self::get_write_register(prev) {
                             // Check if current instruction reads that same register
-                            let read_regs = Self::get_read_registers(&instr);
-                            if read_regs.contains(&written_reg) {
-                                warns.push(format!(
-                                    "Line {}: RAW Hazard. Reading R{} immediately after writing may yield old value due to pipeline latency. Insert a NOOP.", 
-                                    source_line, written_reg
-                                ));
+                            if Self::get_read_registers(&instr).contains(&written_reg) {
+                                let reads_a = instr.a.type_ == OperandType::Register && instr.a.data == written_reg
+                                    && instr.operation.spec().reads_a.applies(instr.args);
+                                let span = if reads_a { line_spans.a } else { line_spans.b };
+                                diagnostics.push(Diagnostic::new(
+                                    Code::RawHazard, source_line, span.unwrap_or_default(),
+                                    format!("RAW Hazard. Reading R{} immediately after writing may yield old value due to pipeline latency. Insert a NOOP.", written_reg),
+                                )
+                                .with_related(prev.source_line, prev_spans.a.unwrap_or_default(), format!("R{} is written here", written_reg))
+                                .with_fix("Insert a NOOP", source_line, Span::new(line_spans.op.start, line_spans.op.start), Self::noop_before(texts[line.number as usize - 1])));
                             }
                         }
                     }
 
-                    if !warns.is_empty() {
-                        warnings.extend(warns);
-                    }
                     instructions.push(instr);
-                    addr_counter += 1;
+                    spans.push(line_spans);
+                },
+                Err(d) => {
+                    diagnostics.push(d);
+                    instructions.push(placeholder);
+                    spans.push(LineSpans::default());
                 },
-                Ok(None) => {}, // Empty or comment or just label
-                Err(e) => {
-                    errors.push(format!("Line {}: {}", source_line, e));
+            }
+        }
+
+        (instructions, diagnostics)
+    }
+
+    /// Labels, `.equ` constants and `.define` aliases. Reports names that aren't
+    /// identifiers or read as a mnemonic, register or number, names defined twice
+    /// (the first definition wins), and labels nothing refers to.
+    fn collect_symbols(source: &Source, diagnostics: &mut Vec<Diagnostic>) -> SymbolTable {
+        let mut symbols = SymbolTable::default();
+        let addresses = source.addresses();
+        for (line, &address) in source.lines.iter().zip(&addresses) {
+            if let Some(label) = &line.label {
+                let symbol = Symbol { value: SymbolValue::Address(address), line: line.number, span: label.span };
+                if let Err(d) = Self::define_symbol(&mut symbols, "Label", &label.name, symbol) {
+                    diagnostics.push(d);
+                }
+            }
+            if let Some(Statement::Directive(directive)) = &line.statement {
+                if matches!(directive.name.as_str(), ".EQU" | ".DEFINE") {
+                    if let Err(d) = Self::parse_definition(&mut symbols, directive, line.number) {
+                        diagnostics.push(d);
+                    }
                 }
             }
         }
 
-        (instructions, errors, warnings)
+        // Definitions may refer to names defined further down, so they're checked once all are known
+        for (line, statement, _) in source.statements() {
+            let Statement::Directive(directive) = statement else { continue };
+            let (".EQU" | ".DEFINE", [name, value]) = (directive.name.as_str(), directive.args.as_slice()) else { continue };
+            let Some(name) = name.name() else { continue };
+            // A duplicate was already reported
+            if symbols.get(name).is_none_or(|s| s.line != line.number) {
+                continue;
+            }
+            let symbol = Expr::Symbol(name.to_string(), value.span);
+            let resolved = match directive.name.as_str() {
+                ".EQU" => symbols.eval(&symbol).map(drop),
+                _ => symbols.operand(&OperandExpr::Value(symbol)).map(drop),
+            };
+            if let Err(e) = resolved {
+                diagnostics.push(Diagnostic::new(Code::InvalidDirective, line.number, value.span, e));
+            }
+        }
+
+        // Names in operands and in definitions (not the names being defined)
+        let used: HashSet<&str> = source.statements()
+            .flat_map(|(_, statement, _)| match statement {
+                Statement::Instruction(node) => node.operands.iter().collect::<Vec<_>>(),
+                Statement::Directive(directive) => match directive.name.as_str() {
+                    ".EQU" | ".DEFINE" => directive.args.iter().skip(1).collect(),
+                    _ => directive.args.iter().collect(),
+                },
+            })
+            .flat_map(|operand| operand.symbols())
+            .map(|(name, _)| name)
+            .collect();
+        // A label at address 0 marks the entry point, so it is fine unused
+        for (line, label, _) in source.labels().filter(|&(_, _, address)| address != 0) {
+            let first = symbols.get(&label.name).is_some_and(|symbol| symbol.line == line.number && symbol.span == label.span);
+            if first && !used.contains(label.name.as_str()) {
+                diagnostics.push(
+                    Diagnostic::new(Code::UnusedLabel, line.number, label.span, format!("Label {} is never used.", label.name))
+                        .with_fix("Remove the label", line.number, Span::new(label.span.start, label.colon.end), ""),
+                );
+            }
+        }
+        symbols
     }
 
-    fn check_warnings(instr: &Instruction, line: i32) -> Vec<String> {
+    /// `.equ NAME value` or `.define NAME operand`.
+    fn parse_definition(symbols: &mut SymbolTable, directive: &DirectiveNode, source_line: i32) -> Result<(), Diagnostic> {
+        let invalid = |span: Span, message: String| Diagnostic::new(Code::InvalidDirective, source_line, span, message);
+        let (name, value) = match directive.args.as_slice() {
+            [name, value] => (name, value),
+            _ => return Err(invalid(directive.span, format!("{} expects a name and a value.", directive.name))),
+        };
+        let name_text = name.name().ok_or_else(|| invalid(name.span, format!("{} expects a name first.", directive.name)))?;
+        let expr = value.expr.clone().map_err(|e| invalid(value.span, e))?;
+        let symbol_value = match (directive.name.as_str(), expr) {
+            (".EQU", OperandExpr::Value(expr)) => SymbolValue::Constant(expr),
+            (".EQU", _) => return Err(invalid(value.span, ".EQU needs a value. Use .DEFINE to name a register, address or port.".to_string())),
+            (_, expr) => SymbolValue::Alias(expr),
+        };
+        let symbol = Symbol { value: symbol_value, line: source_line, span: name.span };
+        Self::define_symbol(symbols, "Constant", name_text, symbol)
+    }
+
+    fn define_symbol(symbols: &mut SymbolTable, kind: &str, name: &str, symbol: Symbol) -> Result<(), Diagnostic> {
+        let (line, span) = (symbol.line, symbol.span);
+        Self::check_symbol_name(kind, name).map_err(|e| Diagnostic::new(Code::InvalidLabel, line, span, e))?;
+        symbols.define(name, symbol).map_err(|first| {
+            Diagnostic::new(Code::DuplicateLabel, line, span, format!("{} {} is already defined.", kind, name))
+                .with_related(first.line, first.span, "First defined here")
+        })
+    }
+
+    /// Names are a letter or `_` followed by letters, digits and `_`, and must not
+    /// read as anything else an operand can be.
+    fn check_symbol_name(kind: &str, name: &str) -> Result<(), String> {
+        let mut chars = name.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!(
+                "{} is not a valid {}. {}s start with a letter or _ and contain only letters, digits and _.",
+                name, kind.to_lowercase(), kind,
+            ));
+        }
+        if Self::parse_operation(name).is_ok() {
+            return Err(format!("{} {} has the same name as an instruction.", kind, name));
+        }
+        if name.strip_prefix('R').is_some_and(|n| parse_number(n).is_ok()) {
+            return Err(format!("{} {} looks like a register.", kind, name));
+        }
+        if parse_number(name).is_ok() {
+            return Err(format!("{} {} looks like a number.", kind, name));
+        }
+        Ok(())
+    }
+
+    /// Handles `.BANK n`, which tells the assembler which RAM bank the page register
+    /// selects for the code that follows. `.EQU` and `.DEFINE` are read by `collect_symbols`.
+    fn parse_directive(directive: &DirectiveNode, source_line: i32, config: &AsmConfig, symbols: &SymbolTable, bank: &mut (u8, Option<i32>)) -> Result<(), Diagnostic> {
+        match directive.name.as_str() {
+            ".BANK" => {
+                let arg = directive.args.first().ok_or_else(|| Diagnostic::new(
+                    Code::InvalidDirective, source_line, directive.span, ".BANK expects a bank number",
+                ))?;
+                let n = match &arg.expr {
+                    Ok(OperandExpr::Value(expr)) => symbols.eval(expr)
+                        .map_err(|e| Diagnostic::new(Code::InvalidDirective, source_line, arg.span, e))?,
+                    Ok(_) => return Err(Diagnostic::new(Code::InvalidDirective, source_line, arg.span, ".BANK expects a bank number")),
+                    Err(e) => return Err(Diagnostic::new(Code::InvalidDirective, source_line, arg.span, e.clone())),
+                };
+                if n < 0 || n >= config.ram_banks as i32 {
+                    return Err(Diagnostic::new(
+                        Code::InvalidDirective, source_line, arg.span,
+                        format!("Bank {} does not exist (0-{}).", n, config.ram_banks as i32 - 1),
+                    ));
+                }
+                *bank = (n as u8, Some(source_line));
+                Ok(())
+            },
+            ".EQU" | ".DEFINE" => Ok(()),
+            other => Err(Diagnostic::new(Code::InvalidDirective, source_line, directive.span, format!("Unknown directive: {}", other))),
+        }
+    }
+
+    /// Memory operands are always flat addresses (`#37` is bank 2, offset 5; `#3` is
+    /// bank 0, offset 3). They are encoded as the offset, and must belong to the bank
+    /// selected by `.BANK`. Addresses past the end of RAM are left alone
+    /// (`check_warnings` reports them).
+    fn resolve_bank(instr: &mut Instruction, spans: &LineSpans, line: i32, config: &AsmConfig, bank: (u8, Option<i32>)) -> Vec<Diagnostic> {
         let mut warnings = Vec::new();
-        let op = instr.operation;
+        let spec = instr.operation.spec();
+        let (operand, span) = if spec.a == OperandKind::Memory {
+            (&mut instr.a, spans.a)
+        } else if spec.b == OperandKind::Memory {
+            (&mut instr.b, spans.b)
+        } else {
+            return warnings;
+        };
+        if operand.type_ != OperandType::MemoryAddress
+            || operand.data < 0
+            || operand.data >= config.ram_size() {
+            return warnings;
+        }
+
+        let (bank, bank_line) = bank;
+        let addr_bank = operand.data / BANK_SIZE as i32;
+        if addr_bank != bank as i32 {
+            let mut warning = Diagnostic::new(
+                Code::BankMismatch, line, span.unwrap_or_default(),
+                format!("Memory address #{} is in bank {}, but this code assumes bank {} (see .BANK).", operand.data, addr_bank, bank),
+            );
+            if let Some(bank_line) = bank_line {
+                warning = warning.with_related(bank_line, Span::default(), format!("Bank {} is selected here", bank));
+            }
+            // Probably meant as an offset into the selected bank
+            if let Some(span) = span {
+                let flat = bank as i32 * BANK_SIZE as i32 + operand.data % BANK_SIZE as i32;
+                warning = warning.with_fix(format!("Change to #{}", flat), line, span, format!("#{}", flat));
+            }
+            warnings.push(warning);
+        }
+        operand.data %= BANK_SIZE as i32;
+        warnings
+    }
+
+    /// Text inserted before an instruction to put a NOOP ahead of it. It goes after any
+    /// label, so jumps to the label still run the NOOP, and the instruction moves to
+    /// its own line with the same indentation.
+    fn noop_before(text: &str) -> String {
+        let indent: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+        format!("NOOP\n{}", indent)
+    }
+
+    /// Values are one byte, so out-of-range ones (already warned about) keep their
+    /// low 8 bits, as the CPU would.
+    fn wrap_immediates(instr: &mut Instruction) {
+        let spec = instr.operation.spec();
+        for (kind, operand) in [(spec.a, &mut instr.a), (spec.b, &mut instr.b)] {
+            if kind == OperandKind::Immediate && operand.type_ == OperandType::Immediate {
+                operand.data &= 0xFF;
+            }
+        }
+    }
+
+    fn check_warnings(instr: &Instruction, spans: &LineSpans, line: i32, config: &AsmConfig) -> Vec<Diagnostic> {
+        let mut warnings = Vec::new();
+        let spec = instr.operation.spec();
         let a = &instr.a;
         let b = &instr.b;
+        let a_span = spans.a.unwrap_or_default();
 
         // 1. Check writing to R0
-        let writes_to_a = matches!(op, 
-            Operation::IMM | Operation::MOV | Operation::ADD | Operation::ADDC | 
-            Operation::SUB | Operation::AND | Operation::OR | Operation::XOR | 
-            Operation::SHR | Operation::NOT | Operation::LOAD | Operation::POP | 
-            Operation::INP
-        );
-
-        if writes_to_a && a.type_ == OperandType::Register && a.data == 0 {
-             let safe = match op {
-                 Operation::ADD | Operation::ADDC | Operation::SUB | 
-                 Operation::AND | Operation::OR | Operation::XOR => {
-                     instr.args == OperationArgs::X
-                 },
-                 _ => false
-             };
-
-             if !safe {
-                 warnings.push(format!("Line {}: Writing to Register 0 (Zero Register) effectively does nothing.", line));
-             }
+        if spec.writes_a.applies(instr.args) && a.type_ == OperandType::Register && a.data == 0 {
+            warnings.push(Diagnostic::new(Code::ZeroRegisterWrite, line, a_span, "Writing to Register 0 (Zero Register) effectively does nothing."));
         }
         
-        // 2. Out of bounds Immediate
+        // 2. Out of bounds Immediate (jump targets are line numbers)
+        let wrapped = |operand: &Operand, span: Option<Span>| {
+            let warning = Diagnostic::new(
+                Code::ImmediateRange, line, span.unwrap_or_default(),
+                format!("Immediate value {} is out of 8-bit range (-128 to 255). It will be wrapped.", operand.data),
+            );
+            // No source to edit for a decoded ROM
+            match span {
+                Some(span) => warning.with_fix(format!("Replace with {}", operand.data & 0xFF), line, span, (operand.data & 0xFF).to_string()),
+                None => warning,
+            }
+        };
         if a.type_ == OperandType::Immediate
             && (a.data < 0 || a.data > 255)
-                 && !matches!(op, Operation::JMP | Operation::CALL | Operation::BIE | Operation::BIG | Operation::BIL | Operation::BIO) {
-                     warnings.push(format!("Line {}: Immediate value {} is out of 8-bit range (0-255). It will be wrapped.", line, a.data));
+                 && spec.a != OperandKind::Label {
+                     warnings.push(wrapped(a, spans.a));
                  }
         if b.type_ == OperandType::Immediate
             && (b.data < 0 || b.data > 255) {
-                 warnings.push(format!("Line {}: Immediate value {} is out of 8-bit range (0-255). It will be wrapped.", line, b.data));
+                 warnings.push(wrapped(b, spans.b));
             }
 
-        // 3. Port out of bounds
-        if op == Operation::OUT
-             && a.type_ == OperandType::Port
-                 && (a.data < 0 || a.data > 7) {
-                     warnings.push(format!("Line {}: Port %{} is out of range (0-7).", line, a.data));
-                 }
-
-        // 4. RAM out of bounds
-        if op == Operation::STORE
-             && a.type_ == OperandType::MemoryAddress
-                 && (a.data < 0 || a.data > 15) {
-                     warnings.push(format!("Line {}: Memory address #{} is out of RAM range (0-15).", line, a.data));
-                 }
-        if op == Operation::LOAD
-             && b.type_ == OperandType::MemoryAddress
-                 && (b.data < 0 || b.data > 15) {
-                     warnings.push(format!("Line {}: Memory address #{} is out of RAM range (0-15).", line, b.data));
-                 }
+        // 3. Port and RAM out of bounds
+        let ram_max = config.ram_size() - 1;
+        for (kind, operand, span) in [(spec.a, a, spans.a), (spec.b, b, spans.b)] {
+            let span = span.unwrap_or_default();
+            if kind == OperandKind::Port
+                && operand.type_ == OperandType::Port
+                    && (operand.data < 0 || operand.data > 255) {
+                        warnings.push(Diagnostic::new(Code::PortRange, line, span, format!("Port %{} is out of range (0-255).", operand.data)));
+                    }
+            if kind == OperandKind::Memory
+                && operand.type_ == OperandType::MemoryAddress
+                    && (operand.data < 0 || operand.data > ram_max) {
+                        warnings.push(Diagnostic::new(
+                            Code::MemoryRange, line, span,
+                            format!("Memory address #{} is out of RAM range (0-{}).", operand.data, ram_max),
+                        ));
+                    }
+        }
 
         warnings
     }
@@ -591,211 +1349,267 @@ impl Parser {
 
     /// Returns the register index if the instruction writes to a register.
     fn get_write_register(instr: &Instruction) -> Option<i32> {
-        // Must target a register
-        if instr.a.type_ != OperandType::Register {
-            return None;
-        }
-
-        match instr.operation {
-            Operation::IMM | Operation::MOV | Operation::LOAD | Operation::POP | Operation::INP => Some(instr.a.data),
-            Operation::ADD | Operation::ADDC | Operation::SUB | Operation::AND | Operation::OR | Operation::XOR => {
-                // 'X' prefix writes to ACC only, not the Register
-                if instr.args == OperationArgs::X {
-                    None
-                } else {
-                    Some(instr.a.data)
-                }
-            },
-            Operation::SHR | Operation::NOT => Some(instr.a.data),
-            _ => None
-        }
+        // Must target a register. 'X' prefix writes to ACC only, not the Register
+        let writes = instr.operation.spec().writes_a.applies(instr.args);
+        (writes && instr.a.type_ == OperandType::Register).then_some(instr.a.data)
     }
 
     /// Returns a list of registers that are read by the instruction.
     fn get_read_registers(instr: &Instruction) -> Vec<i32> {
+        let spec = instr.operation.spec();
         let mut reads = Vec::new();
 
-        // Check Operand A (Source)
-        if instr.a.type_ == OperandType::Register {
-            match instr.operation {
-                // Math ops read A unless using U/X (which use ACC as source A)
-                Operation::ADD | Operation::ADDC | Operation::SUB | Operation::AND | Operation::OR | Operation::XOR => {
-                    if instr.args != OperationArgs::U && instr.args != OperationArgs::X {
-                        reads.push(instr.a.data);
-                    }
-                },
-                Operation::PUSH | Operation::ROUT => {
-                    reads.push(instr.a.data);
-                },
-                _ => {}
-            }
+        // Math ops read A unless using U/X (which use ACC as source A)
+        if instr.a.type_ == OperandType::Register && spec.reads_a.applies(instr.args) {
+            reads.push(instr.a.data);
         }
-
-        // Check Operand B (Source)
-        if instr.b.type_ == OperandType::Register {
-            match instr.operation {
-                Operation::MOV | Operation::ADD | Operation::ADDC | Operation::SUB | 
-                Operation::AND | Operation::OR | Operation::XOR | 
-                Operation::SHR | Operation::NOT | Operation::OUT | 
-                Operation::ROUT | Operation::STORE => {
-                    reads.push(instr.b.data);
-                },
-                _ => {}
-            }
+        if instr.b.type_ == OperandType::Register && spec.reads_b {
+            reads.push(instr.b.data);
         }
 
         reads
     }
 
-    fn parse_line(line: &str, address: i32, source_line: i32, labels: &HashMap<String, i32>) -> Result<Option<Instruction>, String> {
-        let mut clean = line.split(';').next().unwrap_or("").trim().to_uppercase();
-        
-        if let Some(idx) = clean.find(':') {
-            clean = clean.get(idx+1..).unwrap_or("").trim().to_string();
-        }
-
-        if clean.is_empty() { return Ok(None); }
-
-        let tokens: Vec<&str> = clean.split_whitespace().collect();
-        if tokens.is_empty() { return Ok(None); }
-
-        let (op, args) = Self::parse_operation(tokens[0])?;
+    fn parse_instruction(node: &InstructionNode, address: i32, source_line: i32, symbols: &SymbolTable) -> Result<(Instruction, LineSpans), Diagnostic> {
+        let (op, args) = Self::parse_operation(&node.mnemonic)
+            .map_err(|(code, e)| {
+                let d = Diagnostic::new(code, source_line, node.span, e);
+                match code {
+                    Code::PrefixNotAllowed => d.with_fix("Remove the prefix", source_line, node.span, &node.mnemonic[1..]),
+                    _ => d,
+                }
+            })?;
+        Self::check_operand_count(node, op, args, source_line)?;
         let needed = Self::get_needed_operands(op, args);
+        let mut spans = LineSpans { op: node.span, a: None, b: None };
 
-        let mut token_idx = 1;
+        let mut operands = node.operands.iter();
         let mut val_a = Operand::new(OperandType::Immediate, 0);
         let mut val_b = Operand::new(OperandType::Immediate, 0);
-
-        if needed.0
-            && token_idx < tokens.len() {
-                val_a = Self::parse_operand(tokens[token_idx], labels)?;
-                token_idx += 1;
-            }
-        if needed.1
-            && token_idx < tokens.len() {
-                val_b = Self::parse_operand(tokens[token_idx], labels)?;
-                token_idx += 1;
+        let operand = |operand: &OperandNode, kind: OperandKind| {
+            let mut value = operand.expr.clone().and_then(|expr| symbols.operand(&expr))
+                .map_err(|e| Diagnostic::new(Code::InvalidOperand, source_line, operand.span, e))?;
+            Self::check_operand_kind(node, operand, kind, &value, op, args, source_line)?;
+            // Negative values are two's complement bytes: -1 is 255
+            if kind == OperandKind::Immediate && (-128..0).contains(&value.data) {
+                value.data += 256;
             }
+            Ok(value)
+        };
+
+        let spec = op.spec();
+        if needed.0 {
+            let node = operands.next().unwrap();
+            val_a = operand(node, spec.a)?;
+            spans.a = Some(node.span);
+        }
+        if needed.1 {
+            let node = operands.next().unwrap();
+            val_b = operand(node, spec.b)?;
+            spans.b = Some(node.span);
+        }
 
-        Ok(Some(Instruction {
+        Ok((Instruction {
             operation: op,
             args,
             a: val_a,
             b: val_b,
             address,
             source_line,
-        }))
+        }, spans))
     }
 
-    fn parse_operation(s: &str) -> Result<(Operation, OperationArgs), String> {
-        if let Some(op) = Self::match_op(s) {
+    /// Missing and extra operands are errors, naming what the mnemonic expects.
+    fn check_operand_count(node: &InstructionNode, op: Operation, args: OperationArgs, source_line: i32) -> Result<(), Diagnostic> {
+        let spec = op.spec();
+        let expected = spec.operands(args);
+        let found = &node.operands;
+        if found.len() == expected.len() {
+            return Ok(());
+        }
+
+        let expects = match expected.len() {
+            0 => format!("{} takes no operands", node.mnemonic),
+            1 => format!("{} expects 1 operand ({})", node.mnemonic, spec.signature(args)),
+            n => format!("{} expects {} operands ({})", node.mnemonic, n, spec.signature(args)),
+        };
+        if found.len() < expected.len() {
+            let missing: Vec<&str> = expected[found.len()..].iter().map(|k| k.syntax()).collect();
+            // Point just past the last thing written
+            let end = found.last().map_or(node.span.end, |operand| operand.span.end);
+            return Err(Diagnostic::new(
+                Code::OperandCount, source_line, node.span,
+                format!("{}: missing the {}.", expects, missing.join(" and the ")),
+            )
+            .with_related(source_line, Span::new(end, end), "Expected here"));
+        }
+
+        let extra = &found[expected.len()..];
+        let span = Span::new(extra[0].span.start, extra[extra.len() - 1].span.end);
+        let keep_end = match expected.len() {
+            0 => node.span.end,
+            n => found[n - 1].span.end,
+        };
+        let noun = if extra.len() == 1 { "operand" } else { "operands" };
+        Err(Diagnostic::new(
+            Code::OperandCount, source_line, span,
+            format!("{}: {} extra {} found.", expects, extra.len(), noun),
+        )
+        .with_fix(format!("Remove the extra {}", noun), source_line, Span::new(keep_end, span.end), ""))
+    }
+
+    /// The operand must be written the way its slot expects (`%1` for a port, not `1`).
+    /// Values and labels are interchangeable, since a label is just an address.
+    fn check_operand_kind(node: &InstructionNode, operand: &OperandNode, kind: OperandKind, value: &Operand, op: Operation, args: OperationArgs, source_line: i32) -> Result<(), Diagnostic> {
+        let matches = match kind {
+            OperandKind::Register => value.type_ == OperandType::Register,
+            OperandKind::Immediate | OperandKind::Label => value.type_ == OperandType::Immediate,
+            OperandKind::Memory => value.type_ == OperandType::MemoryAddress,
+            OperandKind::Port => value.type_ == OperandType::Port,
+            OperandKind::None => true,
+        };
+        if matches {
+            return Ok(());
+        }
+
+        // Judged by what the operand resolved to, so a `.define` alias reads as what it stands for
+        let found = match value.type_ {
+            OperandType::Register => "a register",
+            OperandType::MemoryAddress => "a #address",
+            OperandType::Port => "a %port",
+            _ => "a value",
+        };
+        let d = Diagnostic::new(
+            Code::OperandType, source_line, operand.span,
+            format!("{} expects {}: this is {}, not a {}.", node.mnemonic, op.spec().signature(args), found, kind.syntax()),
+        );
+
+        // A bare number was probably meant as the right kind
+        let fix = match (kind, &operand.expr) {
+            (OperandKind::Register, Ok(OperandExpr::Value(Expr::Number(n)))) if (0..8).contains(n) => Some(format!("R{}", n)),
+            (OperandKind::Memory, Ok(OperandExpr::Value(Expr::Number(n)))) => Some(format!("#{}", n)),
+            (OperandKind::Port, Ok(OperandExpr::Value(Expr::Number(n)))) => Some(format!("%{}", n)),
+            _ => None,
+        };
+        Err(match fix {
+            Some(text) => d.with_fix(format!("Change to {}", text), source_line, operand.span, text),
+            None => d,
+        })
+    }
+
+    fn parse_operation(s: &str) -> Result<(Operation, OperationArgs), (Code, String)> {
+        if let Some(op) = Operation::from_mnemonic(s) {
             return Ok((op, OperationArgs::None));
         }
         
         // Check prefixes
+        let invalid = || (Code::InvalidOperation, format!("Invalid operation: {}", s));
         let prefix = s.chars().next().unwrap();
-        let suffix = &s[1..];
-        if let Some(op) = Self::match_op(suffix) {
+        let suffix = s.get(1..).ok_or_else(invalid)?;
+        if let Some(op) = Operation::from_mnemonic(suffix) {
             let args = match prefix {
                 'S' => OperationArgs::S,
                 'U' => OperationArgs::U,
                 'X' => OperationArgs::X,
-                _ => return Err(format!("Invalid operation: {}", s)),
+                _ => return Err(invalid()),
             };
+            if !op.spec().prefixes {
+                return Err((Code::PrefixNotAllowed, format!("{} does not take the S/U/X prefixes.", suffix)));
+            }
             return Ok((op, args));
         }
 
-        Err(format!("Invalid operation: {}", s))
-    }
-
-    fn match_op(s: &str) -> Option<Operation> {
-        match s {
-            "NOOP" | "NOP" => Some(Operation::NOOP),
-            "IMM" => Some(Operation::IMM),
-            "MOV" => Some(Operation::MOV),
-            "ADD" => Some(Operation::ADD),
-            "ADDC" => Some(Operation::ADDC),
-            "SUB" => Some(Operation::SUB),
-            "OR" => Some(Operation::OR),
-            "XOR" => Some(Operation::XOR),
-            "AND" => Some(Operation::AND),
-            "SHR" => Some(Operation::SHR),
-            "NOT" => Some(Operation::NOT),
-            "OUT" => Some(Operation::OUT),
-            "ROUT" => Some(Operation::ROUT),
-            "INP" => Some(Operation::INP),
-            "JMP" => Some(Operation::JMP),
-            "BIE" => Some(Operation::BIE),
-            "BIG" => Some(Operation::BIG),
-            "BIL" => Some(Operation::BIL),
-            "BIO" => Some(Operation::BIO),
-            "STORE" => Some(Operation::STORE),
-            "LOAD" => Some(Operation::LOAD),
-            "PUSH" => Some(Operation::PUSH),
-            "POP" => Some(Operation::POP),
-            "CALL" => Some(Operation::CALL),
-            "RET" => Some(Operation::RET),
-            _ => None
-        }
+        Err(invalid())
     }
 
     fn get_needed_operands(op: Operation, args: OperationArgs) -> (bool, bool) {
-        match op {
-            Operation::NOOP | Operation::RET => (false, false),
-            Operation::IMM | Operation::MOV | Operation::SHR | Operation::NOT | 
-            Operation::OUT | Operation::STORE | Operation::LOAD | Operation::ROUT => (true, true),
-            
-            Operation::ADD | Operation::ADDC | Operation::SUB | 
-            Operation::OR | Operation::XOR | Operation::AND => {
-                if args == OperationArgs::X { (false, true) } else { (true, true) }
-            },
+        let spec = op.spec();
+        (spec.needs_a(args), spec.needs_b())
+    }
+}
 
-            Operation::JMP | Operation::BIE | Operation::BIG | 
-            Operation::BIL | Operation::BIO | Operation::INP | 
-            Operation::PUSH | Operation::POP | Operation::CALL => (true, false),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(emu: &Emulator) -> Vec<u8> {
+        (1..8).map(|r| emu.registers.read(r)).collect()
     }
 
-    fn parse_operand(s: &str, labels: &HashMap<String, i32>) -> Result<Operand, String> {
-        let first = s.chars().next().ok_or("Empty operand")?;
-        let rest = &s[1..];
+    #[test]
+    fn failed_lines_keep_their_address() {
+        let e = Emulator::new("JMP end\nFOO\n.bank 0\nend: NOOP\n".to_string());
+        assert_eq!(e.get_errors(), vec!["Line 2: Invalid operation: FOO"]);
+        let addresses: Vec<_> = e.instructions.iter().map(|i| (i.operation, i.address, i.source_line)).collect();
+        assert_eq!(addresses, vec![(Operation::JMP, 0, 1), (Operation::NOOP, 1, 2), (Operation::NOOP, 2, 4)]);
+        assert_eq!(e.instructions[0].a.data, 2);
+        assert_eq!(e.line_addresses(), vec![0, 1, -1, 2]);
+    }
 
-        if first == 'R' || first == '$' {
-            if let Ok(val) = Self::parse_binary(rest) {
-                return Ok(Operand::new(OperandType::Register, val));
-            }
-        } 
-        
-        if first == '#' || first == '@' {
-            let val = Self::parse_binary(rest)?;
-            return Ok(Operand::new(OperandType::MemoryAddress, val));
-        }
-        
-        if first == '%' {
-            let val = Self::parse_binary(rest)?;
-            return Ok(Operand::new(OperandType::Port, val));
+    #[test]
+    fn programs_with_errors_still_jump_to_their_labels() {
+        // Run anyway, as the UI does when errors are ignored
+        let mut e = Emulator::new("CALL f\nIMM R1 1\nJMP end\nMUL R1 R2\nf: IMM R2 2\nRET\nend: IMM R3 3\n".to_string());
+        assert_eq!(e.get_errors().len(), 1);
+        for _ in 0..40 {
+            e.clock();
         }
+        assert_eq!(registers(&e), vec![1, 2, 3, 0, 0, 0, 0]);
+    }
 
-        // Immediate or Label
-        if let Ok(val) = Self::parse_binary(s) {
-             Ok(Operand::new(OperandType::Immediate, val))
-        } else {
-                // Label lookup
-                if let Some(&addr) = labels.get(s) {
-                    Ok(Operand::new(OperandType::Immediate, addr))
-                } 
-                else {
-                      Err(format!("Invalid value or unknown label: {}", s))
-            }
-        }
+    #[test]
+    fn operand_counts() {
+        let e = Emulator::new("IMM R1\nOUT %1 R2 R3\nRET R1\nUADD R2\nXADD\nXADD R2\n".to_string());
+        assert_eq!(e.get_errors(), vec![
+            "Line 1: IMM expects 2 operands (register, value): missing the value.",
+            "Line 2: OUT expects 2 operands (%port, register): 1 extra operand found.",
+            "Line 3: RET takes no operands: 1 extra operand found.",
+            "Line 4: UADD expects 2 operands (register, register): missing the register.",
+            "Line 5: XADD expects 1 operand (register): missing the register.",
+        ]);
+        // The extra operand is flagged, and the fix removes it with the space before it
+        let extra = &e.diagnostics[1];
+        assert_eq!((extra.span.start, extra.span.end), (10, 12));
+        let edit = &extra.fixes[0].edits[0];
+        assert_eq!((edit.start, edit.end, edit.text.as_str()), (9, 12, ""));
     }
 
-    fn parse_binary(s: &str) -> Result<i32, String> {
-        let clean = s.replace('_', "");
-        if clean.starts_with('B') {
-            i32::from_str_radix(&clean[1..], 2).map_err(|_| format!("Invalid binary: {}", s))
-        } else {
-            clean.parse::<i32>().map_err(|_| format!("Invalid number: {}", s))
-        }
+    #[test]
+    fn operand_kinds() {
+        let e = Emulator::new("IMM %3 R1\nSTORE R1 R2\nOUT R1 R2\nOUT 1 R2\nJMP R1\nl: IMM R1 l\nSTORE 5 R1\n".to_string());
+        assert_eq!(e.get_errors(), vec![
+            "Line 1: IMM expects register, value: this is a %port, not a register.",
+            "Line 2: STORE expects #address, register: this is a register, not a #address.",
+            "Line 3: OUT expects %port, register: this is a register, not a %port.",
+            "Line 4: OUT expects %port, register: this is a value, not a %port.",
+            "Line 5: JMP expects label: this is a register, not a label.",
+            "Line 7: STORE expects #address, register: this is a value, not a #address.",
+        ]);
+        // A bare number gets the prefix it was probably meant to have
+        let fix = |i: usize| e.diagnostics[i].fixes.first().map(|f| f.edits[0].text.clone());
+        assert_eq!((fix(0), fix(3), fix(5)), (None, Some("%1".to_string()), Some("#5".to_string())));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn label_rules() {
+        let e = Emulator::new("R1: NOOP\n10: NOOP\nADD: NOOP\nb01: NOOP\nloop: NOOP\nloop: NOOP\nx.y: NOOP\nunused: NOOP\nJMP loop\n".to_string());
+        assert_eq!(e.get_errors(), vec![
+            "Line 1: Label R1 looks like a register.",
+            "Line 2: 10 is not a valid label. Labels start with a letter or _ and contain only letters, digits and _.",
+            "Line 3: Label ADD has the same name as an instruction.",
+            "Line 4: Label B01 looks like a number.",
+            "Line 6: Label LOOP is already defined.",
+            "Line 7: X.Y is not a valid label. Labels start with a letter or _ and contain only letters, digits and _.",
+        ]);
+        let duplicate = e.diagnostics.iter().find(|d| d.line == 6).unwrap();
+        assert_eq!(duplicate.related[0].line, 5);
+        assert_eq!(e.get_warnings(), vec!["Line 8: Label UNUSED is never used."]);
+    }
+
+    #[test]
+    fn entry_label_may_be_unused() {
+        let e = Emulator::new("start:\nIMM R1 1\nhalt: NOOP\n".to_string());
+        assert!(e.get_errors().is_empty(), "{:?}", e.get_errors());
+        assert_eq!(e.get_warnings(), vec!["Line 3: Label HALT is never used."]);
+    }
+}
@@ -0,0 +1,221 @@
+use wasm_bindgen::prelude::*;
+use serde::{Serialize, Deserialize};
+use std::fmt::Write;
+
+use crate::{ROM_SIZE, WORD_BITS};
+
+/// Bytes per instruction word in the byte-oriented formats.
+const WORD_BYTES: usize = WORD_BITS.div_ceil(8) as usize;
+/// Data bytes per Intel HEX record.
+const HEX_RECORD_LEN: usize = 16;
+/// Words per line in a Logisim image.
+const LOGISIM_LINE_WORDS: usize = 8;
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+// --- ROM Images ---
+
+/// File formats for a ROM image. The byte-oriented ones store each word in
+/// `WORD_BYTES` bytes, starting at address 0.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RomFormat {
+    /// Raw bytes, least significant byte first.
+    BinaryLe,
+    /// Raw bytes, most significant byte first.
+    BinaryBe,
+    /// Intel HEX text, bytes most significant first.
+    IntelHex,
+    /// Logisim-evolution "v2.0 raw" memory image, one hex value per word.
+    Logisim,
+}
+
+fn word_bytes(word: u32, format: RomFormat) -> [u8; WORD_BYTES] {
+    let bytes = word.to_le_bytes();
+    let mut out: [u8; WORD_BYTES] = std::array::from_fn(|i| bytes[i]);
+    if format != RomFormat::BinaryLe {
+        out.reverse();
+    }
+    out
+}
+
+fn bytes_to_words(bytes: &[u8], format: RomFormat) -> Result<Vec<u32>, String> {
+    if !bytes.len().is_multiple_of(WORD_BYTES) {
+        return Err(format!("Image is {} bytes, not a whole number of {}-byte words.", bytes.len(), WORD_BYTES));
+    }
+    Ok(bytes.chunks(WORD_BYTES)
+        .map(|chunk| {
+            let fold = |word: u32, &byte: &u8| word << 8 | byte as u32;
+            if format == RomFormat::BinaryLe {
+                chunk.iter().rev().fold(0, fold)
+            } else {
+                chunk.iter().fold(0, fold)
+            }
+        })
+        .collect())
+}
+
+/// `words` as a file in `format`. Text formats are UTF-8.
+pub fn write_rom_image(words: &[u32], format: RomFormat) -> Vec<u8> {
+    let bytes = || words.iter().flat_map(|&w| word_bytes(w, format)).collect::<Vec<u8>>();
+    match format {
+        RomFormat::BinaryLe | RomFormat::BinaryBe => bytes(),
+        RomFormat::IntelHex => {
+            let mut out = String::new();
+            for (i, record) in bytes().chunks(HEX_RECORD_LEN).enumerate() {
+                out.push_str(&hex_record((i * HEX_RECORD_LEN) as u16, 0x00, record));
+            }
+            out.push_str(&hex_record(0, 0x01, &[]));
+            out.into_bytes()
+        },
+        RomFormat::Logisim => {
+            let digits = WORD_BITS.div_ceil(4) as usize;
+            let mut out = format!("{}\n", LOGISIM_HEADER);
+            for line in words.chunks(LOGISIM_LINE_WORDS) {
+                let values: Vec<String> = line.iter().map(|w| format!("{:0digits$x}", w)).collect();
+                out.push_str(&values.join(" "));
+                out.push('\n');
+            }
+            out.into_bytes()
+        },
+    }
+}
+
+/// Words from a file in `format`.
+pub fn read_rom_image(data: &[u8], format: RomFormat) -> Result<Vec<u32>, String> {
+    match format {
+        RomFormat::BinaryLe | RomFormat::BinaryBe => bytes_to_words(data, format),
+        RomFormat::IntelHex => bytes_to_words(&parse_intel_hex(text(data)?)?, format),
+        RomFormat::Logisim => parse_logisim(text(data)?),
+    }
+}
+
+fn text(data: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(data).map_err(|_| "Image is not valid text.".to_string())
+}
+
+// --- Intel HEX ---
+
+fn hex_record(address: u16, kind: u8, data: &[u8]) -> String {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&address.to_be_bytes());
+    record.push(kind);
+    record.extend_from_slice(data);
+    let checksum = record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    record.push(checksum);
+
+    let mut line = String::from(":");
+    for b in record {
+        write!(line, "{:02X}", b).unwrap();
+    }
+    line.push('\n');
+    line
+}
+
+/// Data records as one byte image (gaps are 0). Understands the extended
+/// segment/linear address records, though a ROM never needs them.
+fn parse_intel_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    let mut base = 0usize;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let hex = line.strip_prefix(':').ok_or(format!("Line {}: Record must start with ':'.", line_no))?;
+        // Checked first so the record can be split by byte
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("Line {}: Invalid hex digit.", line_no));
+        }
+        if !hex.len().is_multiple_of(2) || hex.len() < 10 {
+            return Err(format!("Line {}: Malformed record.", line_no));
+        }
+        let record: Vec<u8> = hex.as_bytes().chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect();
+
+        let len = record[0] as usize;
+        if record.len() != len + 5 {
+            return Err(format!("Line {}: Record length doesn't match its byte count.", line_no));
+        }
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(format!("Line {}: Checksum mismatch.", line_no));
+        }
+        let address = u16::from_be_bytes([record[1], record[2]]) as usize;
+        let data = &record[4..4 + len];
+        match record[3] {
+            0x00 => {
+                let start = base.saturating_add(address);
+                if start.saturating_add(len) > ROM_SIZE * WORD_BYTES {
+                    return Err(format!("Line {}: Data is past the end of the {}-word ROM.", line_no, ROM_SIZE));
+                }
+                if start + len > image.len() {
+                    image.resize(start + len, 0);
+                }
+                image[start..start + len].copy_from_slice(data);
+            },
+            0x01 => return Ok(image),
+            0x02 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+            0x04 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+            0x03 | 0x05 => {}, // Start address, meaningless for a ROM
+            kind => return Err(format!("Line {}: Unsupported record type {:02X}.", line_no, kind)),
+        }
+    }
+    Err("Missing end-of-file record.".to_string())
+}
+
+// --- Logisim ---
+
+/// Values are hex, whitespace separated, `#` starts a comment and `N*value`
+/// repeats a value N times.
+fn parse_logisim(text: &str) -> Result<Vec<u32>, String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == LOGISIM_HEADER => {},
+        _ => return Err(format!("Missing \"{}\" header.", LOGISIM_HEADER)),
+    }
+
+    let mut words = Vec::new();
+    for (i, line) in lines {
+        let line = line.split('#').next().unwrap_or("");
+        for token in line.split_whitespace() {
+            let invalid = || format!("Line {}: Invalid value: {}", i + 1, token);
+            let (count, value) = match token.split_once('*') {
+                Some((count, value)) => (count.parse::<usize>().map_err(|_| invalid())?, value),
+                None => (1, token),
+            };
+            let value = u32::from_str_radix(value, 16).map_err(|_| invalid())?;
+            if count > ROM_SIZE - words.len() {
+                return Err(format!("Line {}: Image is larger than the {}-word ROM.", i + 1, ROM_SIZE));
+            }
+            words.extend(std::iter::repeat_n(value, count));
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let words = [0, 1, 0xFFFFFF, 0x123456, 0xABCDEF];
+        for format in [RomFormat::BinaryLe, RomFormat::BinaryBe, RomFormat::IntelHex, RomFormat::Logisim] {
+            assert_eq!(read_rom_image(&write_rom_image(&words, format), format), Ok(words.to_vec()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn bad_intel_hex_is_an_error() {
+        for text in [":0é000000000", ":+F0000000001", ":0000000000\n", ":02000004FFFFFC\n:0100000000FF\n:00000001FF"] {
+            assert!(read_rom_image(text.as_bytes(), RomFormat::IntelHex).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn logisim_repeat_count_is_bounded() {
+        for text in ["v2.0 raw\n1 18446744073709551615*0", "v2.0 raw\n257*0", "v2.0 raw\n200*0 57*1"] {
+            assert!(read_rom_image(text.as_bytes(), RomFormat::Logisim).is_err(), "{}", text);
+        }
+        assert_eq!(read_rom_image(b"v2.0 raw\n3*a 1", RomFormat::Logisim), Ok(vec![10, 10, 10, 1]));
+    }
+}
@@ -0,0 +1,242 @@
+use wasm_bindgen::prelude::*;
+use serde::{Serialize, Deserialize};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+use crate::{ROM_SIZE, WORD_BITS};
+
+/// Minecraft data version written to schematics (Java 1.20.1). WorldEdit upgrades
+/// older block states on paste, so this only needs to be no newer than the world.
+const DATA_VERSION: i32 = 3465;
+
+const AIR: &str = "minecraft:air";
+/// Blocks a schematic may cover, to keep a bad layout from exhausting memory.
+const MAX_VOLUME: i64 = 1 << 24;
+
+// --- ROM Layout ---
+
+/// Where each ROM bit goes in the world, relative to bit 0 of word 0.
+///
+/// Bit `b` of word `w` sits at
+/// `(w % words_per_row) * word_step + (w / words_per_row) * row_step + b * bit_step`,
+/// counting `b` from the MSB when `msb_first` is set. Negative steps are fine; the
+/// schematic is shifted so its lowest corner is at 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RomLayout {
+    pub bit_step: [i32; 3],
+    pub word_step: [i32; 3],
+    pub words_per_row: u32,
+    pub row_step: [i32; 3],
+    pub msb_first: bool,
+    /// Block for a 1 bit.
+    pub one_block: String,
+    /// Block for a 0 bit.
+    pub zero_block: String,
+    /// Pad the program with NOOPs to the full ROM, so pasting overwrites any old program.
+    pub fill_rom: bool,
+}
+
+impl Default for RomLayout {
+    /// Words side by side along X in rows of 16 (rows along Z), bits stacked up Y,
+    /// every other block. Adjust it to match the build.
+    fn default() -> Self {
+        Self {
+            bit_step: [0, 2, 0],
+            word_step: [2, 0, 0],
+            words_per_row: 16,
+            row_step: [0, 0, 2],
+            msb_first: true,
+            one_block: "minecraft:redstone_block".to_string(),
+            zero_block: "minecraft:glass".to_string(),
+            fill_rom: true,
+        }
+    }
+}
+
+impl RomLayout {
+    /// `None` if the position doesn't fit in an `i32`.
+    fn position(&self, word: usize, bit: u32) -> Option<[i32; 3]> {
+        let per_row = self.words_per_row as usize;
+        let (column, row) = ((word % per_row) as i32, (word / per_row) as i32);
+        let bit = bit as i32;
+        let mut pos = [0; 3];
+        for (i, p) in pos.iter_mut().enumerate() {
+            *p = column.checked_mul(self.word_step[i])?
+                .checked_add(row.checked_mul(self.row_step[i])?)?
+                .checked_add(bit.checked_mul(self.bit_step[i])?)?;
+        }
+        Some(pos)
+    }
+}
+
+// --- NBT ---
+
+const TAG_END: u8 = 0;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+
+/// Just enough of an (uncompressed, big-endian) NBT writer for a schematic.
+struct NbtWriter {
+    buf: Vec<u8>,
+}
+
+impl NbtWriter {
+    fn tag(&mut self, tag: u8, name: &str) {
+        self.buf.push(tag);
+        self.str(name);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn begin_compound(&mut self, name: &str) {
+        self.tag(TAG_COMPOUND, name);
+    }
+
+    fn end_compound(&mut self) {
+        self.buf.push(TAG_END);
+    }
+
+    fn short(&mut self, name: &str, value: i16) {
+        self.tag(TAG_SHORT, name);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn int(&mut self, name: &str, value: i32) {
+        self.tag(TAG_INT, name);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn string(&mut self, name: &str, value: &str) {
+        self.tag(TAG_STRING, name);
+        self.str(value);
+    }
+
+    fn byte_array(&mut self, name: &str, bytes: &[u8]) {
+        self.tag(TAG_BYTE_ARRAY, name);
+        self.buf.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn int_array(&mut self, name: &str, values: &[i32]) {
+        self.tag(TAG_INT_ARRAY, name);
+        self.buf.extend_from_slice(&(values.len() as i32).to_be_bytes());
+        for v in values {
+            self.buf.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+}
+
+// --- Schematic ---
+
+/// A gzipped Sponge schematic (v2) of `words` laid out as a ROM, for WorldEdit.
+/// Positions between the bits are air; paste with `//paste -a` to leave the
+/// ROM's wiring alone.
+pub fn rom_schematic(words: &[u32], layout: &RomLayout) -> Result<Vec<u8>, String> {
+    if layout.words_per_row == 0 {
+        return Err("Layout needs at least one word per row.".to_string());
+    }
+    if layout.one_block.is_empty() || layout.zero_block.is_empty() {
+        return Err("Layout needs a block for both 1 and 0 bits.".to_string());
+    }
+    if words.len() > ROM_SIZE {
+        return Err(format!("Program has {} words; the ROM holds {}.", words.len(), ROM_SIZE));
+    }
+    let count = if layout.fill_rom { ROM_SIZE } else { words.len() };
+
+    // (position, bit value) for every ROM bit
+    let mut bits = Vec::with_capacity(count * WORD_BITS as usize);
+    for word_index in 0..count {
+        let word = words.get(word_index).copied().unwrap_or(0);
+        for bit in 0..WORD_BITS {
+            let slot = if layout.msb_first { WORD_BITS - 1 - bit } else { bit };
+            let pos = layout.position(word_index, slot).ok_or("Layout is too large for a schematic.")?;
+            bits.push((pos, word >> bit & 1 == 1));
+        }
+    }
+
+    let min: [i32; 3] = std::array::from_fn(|i| bits.iter().map(|(p, _)| p[i]).min().unwrap_or(0));
+    let max: [i32; 3] = std::array::from_fn(|i| bits.iter().map(|(p, _)| p[i]).max().unwrap_or(0));
+    let size: [i64; 3] = std::array::from_fn(|i| max[i] as i64 - min[i] as i64 + 1);
+    if size.iter().any(|&s| s > i16::MAX as i64) || size.iter().product::<i64>() > MAX_VOLUME {
+        return Err("Layout is too large for a schematic.".to_string());
+    }
+    let (width, height, length) = (size[0] as usize, size[1] as usize, size[2] as usize);
+
+    let mut palette: Vec<&str> = vec![AIR];
+    let zero = palette_id(&mut palette, &layout.zero_block);
+    let one = palette_id(&mut palette, &layout.one_block);
+
+    let mut blocks = vec![0usize; width * height * length];
+    let mut placed = vec![false; blocks.len()];
+    for (pos, value) in bits {
+        let [x, y, z] = [0, 1, 2].map(|i| (pos[i] - min[i]) as usize);
+        let index = x + z * width + y * width * length;
+        if placed[index] {
+            return Err("Layout puts two ROM bits on the same block.".to_string());
+        }
+        placed[index] = true;
+        blocks[index] = if value { one } else { zero };
+    }
+
+    let mut block_data = Vec::with_capacity(blocks.len());
+    for id in blocks {
+        push_varint(&mut block_data, id as u32);
+    }
+
+    let mut nbt = NbtWriter { buf: Vec::new() };
+    nbt.begin_compound("Schematic");
+    nbt.int("Version", 2);
+    nbt.int("DataVersion", DATA_VERSION);
+    nbt.short("Width", width as i16);
+    nbt.short("Height", height as i16);
+    nbt.short("Length", length as i16);
+    nbt.int_array("Offset", &[0, 0, 0]);
+    nbt.int("PaletteMax", palette.len() as i32);
+    nbt.begin_compound("Palette");
+    for (id, block) in palette.iter().enumerate() {
+        nbt.int(block, id as i32);
+    }
+    nbt.end_compound();
+    nbt.byte_array("BlockData", &block_data);
+    nbt.begin_compound("Metadata");
+    nbt.string("Name", "Electron ROM");
+    nbt.end_compound();
+    nbt.end_compound();
+
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(&nbt.buf).map_err(|e| e.to_string())?;
+    gz.finish().map_err(|e| e.to_string())
+}
+
+fn palette_id<'a>(palette: &mut Vec<&'a str>, block: &'a str) -> usize {
+    match palette.iter().position(|&b| b == block) {
+        Some(id) => id,
+        None => {
+            palette.push(block);
+            palette.len() - 1
+        }
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// The default ROM layout, for the host to tweak and pass to `export_schematic`.
+#[wasm_bindgen]
+pub fn default_rom_layout() -> JsValue {
+    serde_wasm_bindgen::to_value(&RomLayout::default()).unwrap()
+}
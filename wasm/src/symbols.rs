@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use crate::{BinaryOp, Expr, Operand, OperandExpr, OperandType, Span, UnaryOp};
+
+// --- Symbols ---
+//
+// Labels, `.equ` constants and `.define` aliases share one namespace. Values
+// are worked out on use, once every label's address is known, so a constant
+// can refer to a label or constant defined further down.
+
+#[derive(Clone, Debug)]
+pub enum SymbolValue {
+    /// A label's ROM address.
+    Address(i32),
+    /// `.equ NAME expr`
+    Constant(Expr),
+    /// `.define NAME operand`, standing in for a whole operand (`R3`, `%1`, `WIDTH*2`).
+    Alias(OperandExpr),
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub value: SymbolValue,
+    /// Where it's defined
+    pub line: i32,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// Adds `name`, or returns the existing definition if there is one.
+    pub fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), &Symbol> {
+        if self.symbols.contains_key(name) {
+            return Err(&self.symbols[name]);
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// The value of a constant expression.
+    pub fn eval(&self, expr: &Expr) -> Result<i32, String> {
+        self.eval_in(expr, &mut Vec::new())
+    }
+
+    /// An operand with its symbols resolved.
+    pub fn operand(&self, expr: &OperandExpr) -> Result<Operand, String> {
+        self.operand_in(expr, &mut Vec::new())
+    }
+
+    /// `stack` holds the symbols being expanded, to catch definitions that use themselves.
+    fn operand_in(&self, expr: &OperandExpr, stack: &mut Vec<String>) -> Result<Operand, String> {
+        match expr {
+            OperandExpr::Register(n) => Ok(Operand::new(OperandType::Register, *n)),
+            OperandExpr::Memory(e) => Ok(Operand::new(OperandType::MemoryAddress, self.eval_in(e, stack)?)),
+            OperandExpr::Port(e) => Ok(Operand::new(OperandType::Port, self.eval_in(e, stack)?)),
+            OperandExpr::Value(Expr::Symbol(name, _)) => match self.symbols.get(name).map(|s| &s.value) {
+                Some(SymbolValue::Alias(alias)) => {
+                    self.enter(name, stack)?;
+                    let operand = self.operand_in(alias, stack);
+                    stack.pop();
+                    operand
+                },
+                _ => Ok(Operand::new(OperandType::Immediate, self.value_in(name, stack)?)),
+            },
+            OperandExpr::Value(e) => Ok(Operand::new(OperandType::Immediate, self.eval_in(e, stack)?)),
+        }
+    }
+
+    fn enter(&self, name: &str, stack: &mut Vec<String>) -> Result<(), String> {
+        if stack.iter().any(|s| s == name) {
+            return Err(format!("{} is defined in terms of itself.", name));
+        }
+        stack.push(name.to_string());
+        Ok(())
+    }
+
+    fn value_in(&self, name: &str, stack: &mut Vec<String>) -> Result<i32, String> {
+        let symbol = self.symbols.get(name).ok_or_else(|| format!("Invalid value or unknown label: {}", name))?;
+        let expr = match &symbol.value {
+            SymbolValue::Address(address) => return Ok(*address),
+            SymbolValue::Constant(expr) | SymbolValue::Alias(OperandExpr::Value(expr)) => expr,
+            SymbolValue::Alias(_) => return Err(format!("{} is a register, address or port, not a value.", name)),
+        };
+        self.enter(name, stack)?;
+        let value = self.eval_in(expr, stack);
+        stack.pop();
+        value
+    }
+
+    fn eval_in(&self, expr: &Expr, stack: &mut Vec<String>) -> Result<i32, String> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            Expr::Symbol(name, _) => self.value_in(name, stack),
+            Expr::Unary(op, e) => {
+                let v = self.eval_in(e, stack)?;
+                Ok(match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => !v,
+                })
+            },
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.eval_in(a, stack)?, self.eval_in(b, stack)?);
+                let shift = || u32::try_from(b).ok().filter(|&b| b < 32).ok_or(format!("Shift by {} is out of range (0-31).", b));
+                Ok(match op {
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div if b == 0 => return Err("Division by zero.".to_string()),
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::And => a & b,
+                    BinaryOp::Or => a | b,
+                    BinaryOp::Xor => a ^ b,
+                    BinaryOp::Shl => a << shift()?,
+                    BinaryOp::Shr => a >> shift()?,
+                })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator;
+
+    fn table(symbols: &[(&str, SymbolValue)]) -> SymbolTable {
+        let mut table = SymbolTable::default();
+        for (name, value) in symbols {
+            table.define(name, Symbol { value: value.clone(), line: 1, span: Span::new(0, 0) }).unwrap();
+        }
+        table
+    }
+
+    fn symbol(name: &str) -> Expr {
+        Expr::Symbol(name.to_string(), Span::new(0, 0))
+    }
+
+    fn binary(op: BinaryOp, a: i32, b: i32) -> Expr {
+        Expr::Binary(op, Box::new(Expr::Number(a)), Box::new(Expr::Number(b)))
+    }
+
+    #[test]
+    fn cycles() {
+        let symbols = table(&[
+            ("A", SymbolValue::Constant(Expr::Binary(BinaryOp::Add, Box::new(symbol("B")), Box::new(Expr::Number(1))))),
+            ("B", SymbolValue::Constant(symbol("A"))),
+            ("X", SymbolValue::Alias(OperandExpr::Value(symbol("X")))),
+            // Using a name twice isn't a cycle
+            ("C", SymbolValue::Constant(Expr::Binary(BinaryOp::Add, Box::new(symbol("D")), Box::new(symbol("D"))))),
+            ("D", SymbolValue::Address(4)),
+        ]);
+        assert_eq!(symbols.eval(&symbol("A")), Err("A is defined in terms of itself.".to_string()));
+        assert_eq!(symbols.operand(&OperandExpr::Value(symbol("X"))), Err("X is defined in terms of itself.".to_string()));
+        assert_eq!(symbols.eval(&symbol("C")), Ok(8));
+
+        // Checked where they're defined, even if never used
+        let e = Emulator::new(".equ A B\n.equ B A\n.define X X\n".to_string());
+        assert_eq!(e.get_errors(), vec![
+            "Line 1: A is defined in terms of itself.",
+            "Line 2: B is defined in terms of itself.",
+            "Line 3: X is defined in terms of itself.",
+        ]);
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        let symbols = SymbolTable::default();
+        assert_eq!(symbols.eval(&binary(BinaryOp::Div, 1, 0)), Err("Division by zero.".to_string()));
+        assert_eq!(symbols.eval(&binary(BinaryOp::Div, i32::MIN, -1)), Ok(i32::MIN));
+        assert_eq!(symbols.eval(&binary(BinaryOp::Shl, 1, 31)), Ok(i32::MIN));
+        assert_eq!(symbols.eval(&binary(BinaryOp::Shl, 1, 32)), Err("Shift by 32 is out of range (0-31).".to_string()));
+        assert_eq!(symbols.eval(&binary(BinaryOp::Shr, 1, -1)), Err("Shift by -1 is out of range (0-31).".to_string()));
+        assert_eq!(symbols.eval(&symbol("Q")), Err("Invalid value or unknown label: Q".to_string()));
+    }
+}
@@ -0,0 +1,400 @@
+use wasm_bindgen::prelude::*;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::devices::Device;
+use crate::{Emulator, BANK_SIZE};
+
+/// First of the mailbox ports every core in a `System` sees.
+pub const MAILBOX_PORT: u8 = 20;
+/// Number of mailbox slots.
+pub const MAILBOX_SLOTS: u8 = 4;
+/// Conflicts kept before the oldest are dropped.
+const MAX_CONFLICTS: usize = 256;
+
+// --- Shared Memory ---
+
+/// How simultaneous writes to the same byte are resolved.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum Arbitration {
+    /// The lowest core index always wins.
+    FixedPriority,
+    /// Priority rotates by one core every system cycle, starting with core 0.
+    RoundRobin,
+}
+
+/// Two or more cores wrote the same address in one system cycle.
+#[derive(Clone, Debug, Serialize)]
+pub struct Conflict {
+    /// "mailbox" or "ram"
+    pub memory: &'static str,
+    pub cycle: u64,
+    pub address: u8,
+    pub winner: usize,
+    pub losers: Vec<usize>,
+}
+
+/// Memory several cores can read and write.
+///
+/// Writes are queued and committed together at the end of the system cycle, so
+/// the outcome never depends on the order cores happen to be clocked in. A core
+/// reads its own writes straight away; other cores see them from the next cycle.
+pub struct SharedMemory {
+    name: &'static str,
+    data: Vec<u8>,
+    /// (core, address, value) written this cycle
+    pending: Vec<(usize, u8, u8)>,
+    conflicts: Vec<Conflict>,
+}
+
+impl SharedMemory {
+    pub fn new(name: &'static str, len: usize) -> Self {
+        Self {
+            name,
+            data: vec![0; len],
+            pending: Vec::new(),
+            conflicts: Vec::new(),
+        }
+    }
+
+    /// The byte as `core` sees it: its own latest write this cycle, if any.
+    pub fn read(&self, core: usize, address: u8) -> u8 {
+        self.pending.iter()
+            .rev()
+            .find(|&&(writer, a, _)| writer == core && a == address)
+            .map(|&(_, _, value)| value)
+            .or_else(|| self.data.get(address as usize).copied())
+            .unwrap_or(0)
+    }
+
+    pub fn write(&mut self, core: usize, address: u8, value: u8) {
+        if (address as usize) < self.data.len() {
+            self.pending.push((core, address, value));
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    /// Apply this cycle's writes. `first` is the core with the highest priority;
+    /// priority then descends through the core indices, wrapping at `cores`.
+    fn commit(&mut self, cycle: u64, first: usize, cores: usize) {
+        let rank = |core: usize| (core + cores - first) % cores;
+        // By address, then priority, then latest write first
+        let mut pending: Vec<_> = std::mem::take(&mut self.pending).into_iter().rev().collect();
+        pending.sort_by_key(|&(core, address, _)| (address, rank(core)));
+
+        let mut i = 0;
+        while i < pending.len() {
+            let (winner, address, value) = pending[i];
+            let mut losers = Vec::new();
+            i += 1;
+            while i < pending.len() && pending[i].1 == address {
+                // A core's earlier writes to the same byte were replaced by its latest
+                if pending[i].0 != winner && !losers.contains(&pending[i].0) {
+                    losers.push(pending[i].0);
+                }
+                i += 1;
+            }
+
+            self.data[address as usize] = value;
+            if !losers.is_empty() {
+                if self.conflicts.len() >= MAX_CONFLICTS {
+                    self.conflicts.remove(0);
+                }
+                self.conflicts.push(Conflict { memory: self.name, cycle, address, winner, losers });
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data.fill(0);
+        self.pending.clear();
+        self.conflicts.clear();
+    }
+}
+
+/// One core's view of a `SharedMemory`, used for RAM accesses.
+pub(crate) struct SharedRam {
+    pub memory: Rc<RefCell<SharedMemory>>,
+    pub core: usize,
+}
+
+/// One core's view of the mailbox, attached to its port bus.
+struct MailboxPort {
+    memory: Rc<RefCell<SharedMemory>>,
+    core: usize,
+}
+
+impl Device for MailboxPort {
+    fn read(&mut self, port: u8) -> Option<u8> {
+        Some(self.peek(port))
+    }
+
+    fn write(&mut self, port: u8, value: u8) {
+        self.memory.borrow_mut().write(self.core, port, value);
+    }
+
+    fn peek(&self, port: u8) -> u8 {
+        self.memory.borrow().read(self.core, port)
+    }
+}
+
+// --- System ---
+
+/// Several `Emulator` cores, each running its own program, clocked together.
+///
+/// Every core sees a mailbox on `MAILBOX_PORT`. Optionally the cores also share
+/// one RAM (stack included) instead of having 16 bytes each. Core `i` is clocked
+/// on every `divider[i]`-th system cycle, so all dividers at 1 is lockstep.
+#[wasm_bindgen]
+pub struct System {
+    cores: Vec<Emulator>,
+    dividers: Vec<u32>,
+    arbitration: Arbitration,
+    mailbox: Rc<RefCell<SharedMemory>>,
+    ram: Rc<RefCell<SharedMemory>>,
+    shared_ram: bool,
+    cycle: u64,
+}
+
+#[wasm_bindgen]
+impl System {
+    #[wasm_bindgen(constructor)]
+    pub fn new(programs: Vec<String>) -> System {
+        let mailbox = Rc::new(RefCell::new(SharedMemory::new("mailbox", MAILBOX_SLOTS as usize)));
+        let cores: Vec<Emulator> = programs.into_iter()
+            .enumerate()
+            .map(|(core, code)| {
+                let mut emu = Emulator::new(code);
+                emu.attach(MAILBOX_PORT, MAILBOX_SLOTS, Box::new(MailboxPort { memory: mailbox.clone(), core }));
+                emu
+            })
+            .collect();
+
+        System {
+            dividers: vec![1; cores.len()],
+            cores,
+            arbitration: Arbitration::FixedPriority,
+            mailbox,
+            ram: Rc::new(RefCell::new(SharedMemory::new("ram", BANK_SIZE as usize))),
+            shared_ram: false,
+            cycle: 0,
+        }
+    }
+
+    pub fn core_count(&self) -> usize {
+        self.cores.len()
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Load a new program into one core and reset it.
+    pub fn load_program(&mut self, core: usize, code: String) {
+        if let Some(emu) = self.cores.get_mut(core) {
+            emu.load_program(code);
+        }
+    }
+
+    /// Clock `core` only on every `divider`-th system cycle (minimum 1).
+    pub fn set_divider(&mut self, core: usize, divider: u32) {
+        if let Some(d) = self.dividers.get_mut(core) {
+            *d = divider.max(1);
+        }
+    }
+
+    /// Use the rotating priority instead of "lowest core wins".
+    pub fn set_round_robin(&mut self, enabled: bool) {
+        self.arbitration = if enabled { Arbitration::RoundRobin } else { Arbitration::FixedPriority };
+    }
+
+    /// Make every core use one shared RAM (stack included) instead of its own.
+    pub fn set_shared_ram(&mut self, enabled: bool) {
+        self.shared_ram = enabled;
+        for (core, emu) in self.cores.iter_mut().enumerate() {
+            let shared = enabled.then(|| SharedRam { memory: self.ram.clone(), core });
+            emu.set_shared_ram(shared);
+        }
+    }
+
+    /// Give every core `banks` RAM banks (see `Emulator::set_ram_banks`), shared RAM included.
+    pub fn set_ram_banks(&mut self, banks: u8) {
+        for emu in &mut self.cores {
+            emu.set_ram_banks(banks);
+        }
+        let banks = self.cores.first().map_or(1, |emu| emu.ram_banks());
+        self.ram = Rc::new(RefCell::new(SharedMemory::new("ram", banks as usize * BANK_SIZE as usize)));
+        self.set_shared_ram(self.shared_ram);
+    }
+
+    /// Reset every core and the shared memories, keeping the loaded programs.
+    pub fn reset(&mut self) {
+        for emu in &mut self.cores {
+            emu.reset();
+        }
+        self.mailbox.borrow_mut().reset();
+        self.ram.borrow_mut().reset();
+        self.cycle = 0;
+    }
+
+    /// Advance one system cycle.
+    pub fn clock(&mut self) {
+        for (emu, &divider) in self.cores.iter_mut().zip(&self.dividers) {
+            if self.cycle.is_multiple_of(divider as u64) {
+                emu.clock();
+            }
+        }
+
+        let cores = self.cores.len().max(1);
+        let first = match self.arbitration {
+            Arbitration::FixedPriority => 0,
+            Arbitration::RoundRobin => (self.cycle % cores as u64) as usize,
+        };
+        self.mailbox.borrow_mut().commit(self.cycle, first, cores);
+        self.ram.borrow_mut().commit(self.cycle, first, cores);
+        self.cycle += 1;
+    }
+
+    pub fn run(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    pub fn get_core_state(&self, core: usize) -> JsValue {
+        match self.cores.get(core) {
+            Some(emu) => emu.get_state(),
+            None => JsValue::NULL,
+        }
+    }
+
+    pub fn get_core_errors(&self, core: usize) -> Vec<String> {
+        self.cores.get(core).map(|emu| emu.get_errors()).unwrap_or_default()
+    }
+
+    pub fn get_core_diagnostics(&self, core: usize) -> JsValue {
+        match self.cores.get(core) {
+            Some(emu) => emu.get_diagnostics(),
+            None => JsValue::NULL,
+        }
+    }
+
+    pub fn get_mailbox(&self) -> Vec<u8> {
+        self.mailbox.borrow().data().to_vec()
+    }
+
+    pub fn get_shared_ram(&self) -> Vec<u8> {
+        self.ram.borrow().data().to_vec()
+    }
+
+    /// Every write conflict so far (mailbox and shared RAM), oldest first.
+    pub fn get_conflicts(&self) -> JsValue {
+        let mut conflicts: Vec<Conflict> = self.mailbox.borrow().conflicts().iter()
+            .chain(self.ram.borrow().conflicts())
+            .cloned()
+            .collect();
+        conflicts.sort_by_key(|c| c.cycle);
+        serde_wasm_bindgen::to_value(&conflicts).unwrap()
+    }
+}
+
+// Rust-side API (not exported to JS)
+impl System {
+    pub fn core(&self, core: usize) -> Option<&Emulator> {
+        self.cores.get(core)
+    }
+
+    pub fn core_mut(&mut self, core: usize) -> Option<&mut Emulator> {
+        self.cores.get_mut(core)
+    }
+
+    pub fn arbitration(&self) -> Arbitration {
+        self.arbitration
+    }
+
+    pub fn mailbox(&self) -> std::cell::Ref<'_, SharedMemory> {
+        self.mailbox.borrow()
+    }
+
+    pub fn shared_ram(&self) -> Option<std::cell::Ref<'_, SharedMemory>> {
+        self.shared_ram.then(|| self.ram.borrow())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cores_read_their_own_writes_at_once() {
+        let mut memory = SharedMemory::new("ram", 16);
+        memory.write(0, 3, 7);
+        assert_eq!((memory.read(0, 3), memory.read(1, 3)), (7, 0));
+        memory.commit(0, 0, 2);
+        assert_eq!((memory.read(0, 3), memory.read(1, 3)), (7, 7));
+    }
+
+    #[test]
+    fn shared_ram_runs_a_single_program_unchanged() {
+        // `PUSH` writes back in the same clock `RET` reads the return address
+        let code = "IMM R1 6\nNOOP\nNOOP\nPUSH R1\nRET\nIMM R2 1\nIMM R3 2\n";
+        let mut alone = Emulator::new(code.to_string());
+        let mut system = System::new(vec![code.to_string()]);
+        system.set_shared_ram(true);
+        let registers = |emu: &Emulator| (1..8).map(|r| emu.registers().read(r)).collect::<Vec<_>>();
+        for cycle in 1..=20 {
+            alone.clock();
+            system.clock();
+            assert_eq!(registers(system.core(0).unwrap()), registers(&alone), "cycle {}", cycle);
+        }
+        assert_eq!(alone.registers().read(3), 2);
+    }
+
+    #[test]
+    fn arbitration() {
+        let mut memory = SharedMemory::new("mailbox", 4);
+        for (first, winner) in [(0, 0), (1, 1), (2, 2), (3, 0)] {
+            for core in (0..3).rev() {
+                memory.write(core, 1, 10 + core as u8);
+            }
+            memory.commit(first as u64, first, 3);
+            assert_eq!(memory.data()[1], 10 + winner as u8, "first {}", first);
+            let conflict = memory.conflicts().last().unwrap();
+            assert_eq!(conflict.winner, winner);
+            assert_eq!(conflict.losers.len(), 2);
+        }
+
+        // Both cores write the mailbox in the same clock
+        let programs = vec!["IMM R1 1\nOUT %20 R1\n".to_string(), "IMM R1 2\nOUT %20 R1\n".to_string()];
+        let mut system = System::new(programs.clone());
+        system.run(10);
+        assert_eq!(system.get_mailbox()[0], 1);
+        let conflicts = system.mailbox().conflicts().to_vec();
+        assert_eq!((conflicts.len(), conflicts[0].winner, conflicts[0].losers.clone()), (1, 0, vec![1]));
+
+        let mut system = System::new(programs);
+        system.set_round_robin(true);
+        system.run(10);
+        let conflict = system.mailbox().conflicts()[0].clone();
+        assert_eq!(conflict.winner as u64, conflict.cycle % 2);
+        assert_eq!(system.get_mailbox()[0], conflict.winner as u8 + 1);
+    }
+
+    #[test]
+    fn clock_dividers() {
+        let mut system = System::new(vec!["NOOP\n".to_string(), "NOOP\n".to_string()]);
+        system.set_divider(1, 3);
+        system.set_divider(0, 0);
+        system.run(9);
+        assert_eq!(system.cycle(), 9);
+        assert_eq!((system.core(0).unwrap().cycles(), system.core(1).unwrap().cycles()), (9, 3));
+    }
+}
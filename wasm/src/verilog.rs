@@ -0,0 +1,199 @@
+use std::fmt::Write;
+
+use crate::{encode_program, Diagnostic, format_instruction, Instruction, PortWrite, ROM_SIZE, WORD_BITS};
+
+// --- Verilog ---
+//
+// A ROM module holding the program, and a testbench that checks a CPU built
+// around it makes the same port writes the emulator did. The CPU under test is
+// expected to look like:
+//
+//   module <cpu>(input clk, input rst,
+//                output port_we, output [7:0] port_addr, output [7:0] port_data);
+//
+// with `port_we` high for one clock per `OUT`/`ROUT`.
+
+/// Module name `export_verilog_rom` uses.
+pub const ROM_MODULE: &str = "electron_rom";
+/// CPU module name the generated testbench instantiates.
+pub const CPU_MODULE: &str = "electron_cpu";
+
+/// A synthesizable ROM: `data` is the word at `addr` (combinational, NOOP past the end).
+pub fn verilog_rom(instructions: &[Instruction], module: &str) -> Result<String, Vec<Diagnostic>> {
+    let words = encode_program(instructions)?;
+    let addr_bits = ROM_SIZE.trailing_zeros();
+    let digits = WORD_BITS.div_ceil(4) as usize;
+
+    let mut v = String::new();
+    writeln!(v, "// Electron 2 program ROM, {} words of {} bits.", words.len(), WORD_BITS).unwrap();
+    writeln!(v, "module {} (", module).unwrap();
+    writeln!(v, "    input  wire [{}:0] addr,", addr_bits - 1).unwrap();
+    writeln!(v, "    output reg  [{}:0] data", WORD_BITS - 1).unwrap();
+    writeln!(v, ");").unwrap();
+    writeln!(v, "    always @(*) begin").unwrap();
+    writeln!(v, "        case (addr)").unwrap();
+    for (address, (instr, word)) in instructions.iter().zip(&words).enumerate() {
+        writeln!(
+            v, "            {}'d{}: data = {}'h{:0digits$x}; // {}",
+            addr_bits, address, WORD_BITS, word, format_instruction(instr)
+        ).unwrap();
+    }
+    writeln!(v, "            default: data = {}'h{:0digits$x}; // NOOP", WORD_BITS, 0).unwrap();
+    writeln!(v, "        endcase").unwrap();
+    writeln!(v, "    end").unwrap();
+    writeln!(v, "endmodule").unwrap();
+    Ok(v)
+}
+
+/// A self-checking testbench for `cpu` (see above) expecting `writes`, in order,
+/// within `cycles` clocks after reset. Set `CHECK_CYCLES` to also require each
+/// write on the same clock as the emulator (clock 1 is the first after reset).
+pub fn verilog_testbench(cpu: &str, writes: &[PortWrite], cycles: u64) -> String {
+    let n = writes.len();
+    let mut v = String::new();
+    writeln!(v, "// Expected port writes recorded from the Electron 2 emulator ({} clocks).", cycles).unwrap();
+    writeln!(v, "`timescale 1ns/1ps").unwrap();
+    writeln!(v, "module {}_tb;", cpu).unwrap();
+    writeln!(v, "    parameter CHECK_CYCLES = 0;").unwrap();
+    writeln!(v, "    localparam CYCLES = {};", cycles).unwrap();
+    writeln!(v, "    localparam N = {};", n).unwrap();
+    writeln!(v).unwrap();
+    writeln!(v, "    reg clk = 0;").unwrap();
+    writeln!(v, "    reg rst = 1;").unwrap();
+    writeln!(v, "    wire       port_we;").unwrap();
+    writeln!(v, "    wire [7:0] port_addr;").unwrap();
+    writeln!(v, "    wire [7:0] port_data;").unwrap();
+    writeln!(v).unwrap();
+    writeln!(v, "    {} dut (", cpu).unwrap();
+    writeln!(v, "        .clk(clk), .rst(rst),").unwrap();
+    writeln!(v, "        .port_we(port_we), .port_addr(port_addr), .port_data(port_data)").unwrap();
+    writeln!(v, "    );").unwrap();
+    writeln!(v).unwrap();
+    writeln!(v, "    always #5 clk = ~clk;").unwrap();
+    writeln!(v).unwrap();
+
+    // Arrays can't be empty, so keep one unused slot when nothing is expected
+    let size = n.max(1);
+    writeln!(v, "    reg [7:0]  exp_port  [0:{}];", size - 1).unwrap();
+    writeln!(v, "    reg [7:0]  exp_data  [0:{}];", size - 1).unwrap();
+    writeln!(v, "    reg [31:0] exp_cycle [0:{}];", size - 1).unwrap();
+    writeln!(v, "    initial begin").unwrap();
+    for (i, w) in writes.iter().enumerate() {
+        writeln!(
+            v, "        exp_port[{i}] = 8'd{}; exp_data[{i}] = 8'd{}; exp_cycle[{i}] = 32'd{};",
+            w.port, w.value, w.cycle
+        ).unwrap();
+    }
+    writeln!(v, "    end").unwrap();
+    writeln!(v).unwrap();
+
+    v.push_str(r#"    integer cycle = 0;
+    integer seen = 0;
+    integer errors = 0;
+
+    always @(posedge clk) begin
+        if (!rst) begin
+            cycle = cycle + 1;
+            if (port_we) begin
+                if (seen >= N) begin
+                    $display("FAIL: unexpected write of %0d to port %0d at clock %0d", port_data, port_addr, cycle);
+                    errors = errors + 1;
+                end else if (port_addr !== exp_port[seen] || port_data !== exp_data[seen]) begin
+                    $display("FAIL: write %0d was %0d to port %0d, expected %0d to port %0d",
+                             seen, port_data, port_addr, exp_data[seen], exp_port[seen]);
+                    errors = errors + 1;
+                end else if (CHECK_CYCLES && cycle !== exp_cycle[seen]) begin
+                    $display("FAIL: write %0d came at clock %0d, expected clock %0d", seen, cycle, exp_cycle[seen]);
+                    errors = errors + 1;
+                end
+                seen = seen + 1;
+            end
+        end
+    end
+
+    initial begin
+        repeat (2) @(posedge clk);
+        #1 rst = 0;
+        repeat (CYCLES) @(posedge clk);
+        #1;
+        if (seen < N) begin
+            $display("FAIL: only %0d of %0d port writes happened", seen, N);
+            errors = errors + 1;
+        end
+        if (errors == 0)
+            $display("PASS: %0d port writes matched", N);
+        else
+            $display("FAIL: %0d errors", errors);
+        $finish;
+    end
+endmodule
+"#);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsmConfig, Emulator, Parser};
+
+    const PROGRAM: &str = "IMM R1 5\nOUT %3 R1\nIMM R2 9\nOUT %4 R2\n";
+
+    #[test]
+    fn records_port_writes() {
+        let emu = Emulator::new(PROGRAM.to_string());
+        let writes = emu.record_port_writes(20);
+        assert_eq!(writes, vec![
+            PortWrite { cycle: 5, port: 3, value: 5 },
+            PortWrite { cycle: 7, port: 4, value: 9 },
+        ]);
+        // Recorded on a copy
+        assert_eq!(emu.cycles(), 0);
+    }
+
+    #[test]
+    fn recording_is_reproducible() {
+        let code = "IN R1 %12\nIN R2 %12\nNOOP\nOUT %3 R1\nOUT %4 R2\n";
+        let mut seeded = Emulator::new(code.to_string());
+        seeded.seed_rng(9);
+        let writes = seeded.record_port_writes(20);
+        assert_eq!(writes.len(), 2);
+        assert_eq!(seeded.record_port_writes(20), writes);
+
+        // Without a seed, the recording uses seed 0
+        let mut zero = Emulator::new(code.to_string());
+        zero.seed_rng(0);
+        let entropy = Emulator::new(code.to_string());
+        assert_eq!(entropy.record_port_writes(20), zero.record_port_writes(20));
+    }
+
+    #[test]
+    fn rom_cases_hold_the_encoded_words() {
+        let (instructions, _) = Parser::parse(PROGRAM.to_string(), &AsmConfig::default());
+        let words = encode_program(&instructions).unwrap();
+        let rom = verilog_rom(&instructions, ROM_MODULE).unwrap();
+        let cases: Vec<String> = rom.lines()
+            .filter(|line| line.contains("'d") && line.contains(": data = "))
+            .map(|line| line.trim().to_string())
+            .collect();
+        let expected: Vec<String> = words.iter()
+            .zip(&instructions)
+            .enumerate()
+            .map(|(address, (word, instr))| format!("8'd{}: data = 24'h{:06x}; // {}", address, word, format_instruction(instr)))
+            .collect();
+        assert_eq!(cases, expected);
+        assert!(rom.starts_with("// Electron 2 program ROM, 4 words of 24 bits.\nmodule electron_rom ("));
+        assert!(rom.contains("default: data = 24'h000000; // NOOP"));
+    }
+
+    #[test]
+    fn testbench_expects_the_recorded_writes() {
+        let writes = Emulator::new(PROGRAM.to_string()).record_port_writes(20);
+        let bench = verilog_testbench(CPU_MODULE, &writes, 20);
+        assert!(bench.contains("module electron_cpu_tb;"));
+        assert!(bench.contains("localparam CYCLES = 20;\n    localparam N = 2;"));
+        assert!(bench.contains("exp_port[0] = 8'd3; exp_data[0] = 8'd5; exp_cycle[0] = 32'd5;"));
+        assert!(bench.contains("exp_port[1] = 8'd4; exp_data[1] = 8'd9; exp_cycle[1] = 32'd7;"));
+        // Nothing expected still declares the arrays
+        assert!(verilog_testbench(CPU_MODULE, &[], 5).contains("reg [7:0]  exp_port  [0:0];"));
+    }
+}